@@ -52,5 +52,8 @@
           rustfmt
         ];
       };
-    });
+    })
+    // {
+      nixosModules.default = import ./nix/module.nix self;
+    };
 }
@@ -0,0 +1,74 @@
+# NixOS module running the site behind a systemd socket, so restarts queue
+# connections in the socket instead of refusing them.
+self: {
+  config,
+  lib,
+  pkgs,
+  ...
+}: let
+  cfg = config.services.site;
+  format = pkgs.formats.yaml {};
+in {
+  options.services.site = {
+    enable = lib.mkEnableOption "the site";
+
+    package = lib.mkOption {
+      type = lib.types.package;
+      default = self.packages.${pkgs.stdenv.hostPlatform.system}.default;
+      description = "The site package to run.";
+    };
+
+    listen = lib.mkOption {
+      type = lib.types.str;
+      default = "/run/site/site.sock";
+      example = "127.0.0.1:8080";
+      description = "Passed to ListenStream= of the socket unit: a unix socket path, a port or an address and port.";
+    };
+
+    socketGroup = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      example = "nginx";
+      description = "Group allowed to connect to a unix socket, e.g. the reverse proxy's.";
+    };
+
+    settings = lib.mkOption {
+      type = format.type;
+      default = {};
+      description = "Contents of config.yaml, see the README for the options.";
+    };
+  };
+
+  config = lib.mkIf cfg.enable {
+    systemd.sockets.site = {
+      wantedBy = ["sockets.target"];
+      listenStreams = [cfg.listen];
+      socketConfig =
+        {
+          SocketMode = "0660";
+        }
+        // lib.optionalAttrs (cfg.socketGroup != null) {
+          SocketGroup = cfg.socketGroup;
+        };
+    };
+
+    systemd.services.site = {
+      requires = ["site.socket"];
+      after = ["site.socket" "network.target"];
+      wantedBy = ["multi-user.target"];
+      environment.SITE_CONFIG = format.generate "site-config.yaml" ({
+          data_dir = "/var/lib/site";
+        }
+        // cfg.settings);
+      serviceConfig = {
+        ExecStart = "${cfg.package}/bin/site";
+        DynamicUser = true;
+        StateDirectory = "site";
+        Restart = "on-failure";
+        # SIGTERM starts draining, give it the drain timeout plus some slack
+        KillSignal = "SIGTERM";
+        TimeoutStopSec = (cfg.settings.drain_timeout_secs or 30) + 5;
+      };
+    };
+  };
+}
@@ -0,0 +1,388 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{mpsc, Arc};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use axum::{
+    body::{self, Bytes, HttpBody},
+    extract::State,
+    http::{header, HeaderMap, Request},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Local, Utc};
+use color_eyre::eyre::Result;
+
+use crate::config::{AccessLogConfig, AccessLogFormat, Rotation};
+use crate::proxy::ClientInfo;
+
+enum Message {
+    Line(String),
+    Flush(mpsc::Sender<()>),
+}
+
+/// The access log, one line per response.
+///
+/// Lines are handed to a writer thread over a channel, which owns the file
+/// and rotates it, so logging never touches the disk on the request path.
+pub struct AccessLog {
+    tx: mpsc::Sender<Message>,
+    format: AccessLogFormat,
+}
+
+/// What gets logged about a request, filled in as the response goes out.
+struct Entry {
+    at: DateTime<Utc>,
+    start: Instant,
+    client: Option<ClientInfo>,
+    method: String,
+    target: String,
+    version: String,
+    status: u16,
+    referrer: Option<String>,
+    user_agent: Option<String>,
+}
+
+impl AccessLog {
+    /// Opens the log at `config.path`, relative to `data_dir` unless absolute.
+    pub fn open(data_dir: &Path, config: &AccessLogConfig) -> Result<Self> {
+        let path = data_dir.join(&config.path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = RotatingFile::open(path, config)?;
+
+        let (tx, rx) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("access-log".to_string())
+            .spawn(move || writer(file, rx))?;
+
+        Ok(Self {
+            tx,
+            format: config.format,
+        })
+    }
+
+    fn log(&self, entry: Entry, bytes: u64) {
+        let line = match self.format {
+            AccessLogFormat::Combined => combined(&entry, bytes),
+            AccessLogFormat::Json => json(&entry, bytes),
+        };
+        if self.tx.send(Message::Line(line)).is_err() {
+            tracing::error!("access log writer is gone, dropping line");
+        }
+    }
+
+    /// Writes out any buffered lines and waits for the write to finish.
+    pub fn flush(&self) {
+        let (ack_tx, ack_rx) = mpsc::channel();
+        if self.tx.send(Message::Flush(ack_tx)).is_ok() {
+            let _ = ack_rx.recv();
+        }
+    }
+}
+
+/// Quotes a header value for the Combined Log Format, escaping what would
+/// break the line the way Apache does.
+fn quoted(value: Option<&str>) -> String {
+    let Some(value) = value else {
+        return "\"-\"".to_string();
+    };
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => out.push_str(&format!("\\x{:02x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn client_ip(entry: &Entry) -> String {
+    entry
+        .client
+        .as_ref()
+        .map_or("-".to_string(), |client| client.ip.to_string())
+}
+
+/// `host - - [time] "request" status bytes "referrer" "user agent" latency`
+fn combined(entry: &Entry, bytes: u64) -> String {
+    let request = format!("{} {} {}", entry.method, entry.target, entry.version);
+    format!(
+        "{} - - [{}] {} {} {} {} {} {:.3}",
+        client_ip(entry),
+        entry.at.format("%d/%b/%Y:%H:%M:%S %z"),
+        quoted(Some(&request)),
+        entry.status,
+        if bytes == 0 {
+            "-".to_string()
+        } else {
+            bytes.to_string()
+        },
+        quoted(entry.referrer.as_deref()),
+        quoted(entry.user_agent.as_deref()),
+        entry.start.elapsed().as_secs_f64(),
+    )
+}
+
+fn json(entry: &Entry, bytes: u64) -> String {
+    serde_json::json!({
+        "time": entry.at.to_rfc3339(),
+        "client_ip": client_ip(entry),
+        "method": entry.method,
+        "target": entry.target,
+        "protocol": entry.version,
+        "status": entry.status,
+        "bytes": bytes,
+        "referrer": entry.referrer,
+        "user_agent": entry.user_agent,
+        "latency_seconds": entry.start.elapsed().as_secs_f64(),
+    })
+    .to_string()
+}
+
+fn header_string(headers: &HeaderMap, name: header::HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+}
+
+/// Middleware writing the access log. The line is written once the body has
+/// been sent (or the client went away), so bytes and latency cover the whole
+/// response, streamed ones included. Sits inside the client info middleware,
+/// for the proxy-resolved client address.
+pub async fn record<B>(
+    State(log): State<Option<Arc<AccessLog>>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(log) = log else {
+        return next.run(request).await;
+    };
+
+    let headers = request.headers();
+    let mut entry = Entry {
+        at: Utc::now(),
+        start: Instant::now(),
+        client: request.extensions().get::<ClientInfo>().cloned(),
+        method: request.method().to_string(),
+        target: request
+            .uri()
+            .path_and_query()
+            .map_or("/".to_string(), |p| p.to_string()),
+        version: format!("{:?}", request.version()),
+        status: 0,
+        referrer: header_string(headers, header::REFERER),
+        user_agent: header_string(headers, header::USER_AGENT),
+    };
+
+    let response = next.run(request).await;
+    entry.status = response.status().as_u16();
+
+    response.map(|inner| {
+        body::boxed(LoggedBody {
+            inner,
+            bytes: 0,
+            entry: Some(entry),
+            log,
+        })
+    })
+}
+
+/// A response body counting the bytes sent through it, which logs its entry
+/// when dropped.
+struct LoggedBody {
+    inner: body::BoxBody,
+    bytes: u64,
+    entry: Option<Entry>,
+    log: Arc<AccessLog>,
+}
+
+impl HttpBody for LoggedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_data(cx);
+        if let Poll::Ready(Some(Ok(data))) = &poll {
+            this.bytes += data.len() as u64;
+        }
+        poll
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for LoggedBody {
+    fn drop(&mut self) {
+        if let Some(entry) = self.entry.take() {
+            self.log.log(entry, self.bytes);
+        }
+    }
+}
+
+/// A log file that moves itself aside once it gets too big or the rotation
+/// period is over. Rotated files get the time of rotation appended to their
+/// name, and only the newest `keep` are kept.
+struct RotatingFile {
+    path: PathBuf,
+    rotation: Rotation,
+    max_size: u64,
+    keep: usize,
+    file: BufWriter<File>,
+    size: u64,
+    period: String,
+}
+
+/// Identifies the rotation period `at` falls in, files are rotated when it
+/// changes.
+fn period(rotation: Rotation, at: DateTime<Local>) -> String {
+    match rotation {
+        Rotation::Never => String::new(),
+        Rotation::Hourly => at.format("%Y-%m-%d %H").to_string(),
+        Rotation::Daily => at.format("%Y-%m-%d").to_string(),
+    }
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, config: &AccessLogConfig) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let metadata = file.metadata()?;
+        // a file left from before a restart belongs to the period it was
+        // last written in
+        let modified = metadata
+            .modified()
+            .map_or_else(|_| Local::now(), DateTime::<Local>::from);
+
+        Ok(Self {
+            period: period(config.rotation, modified),
+            path,
+            rotation: config.rotation,
+            max_size: config.max_size_mb * 1024 * 1024,
+            keep: config.keep,
+            file: BufWriter::new(file),
+            size: metadata.len(),
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let now = Local::now();
+        let period = period(self.rotation, now);
+        let too_big = self.max_size > 0 && self.size + line.len() as u64 + 1 > self.max_size;
+        if self.size > 0 && (too_big || period != self.period) {
+            self.rotate(now)?;
+        }
+        self.period = period;
+
+        self.file.write_all(line.as_bytes())?;
+        self.file.write_all(b"\n")?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    fn rotate(&mut self, now: DateTime<Local>) -> io::Result<()> {
+        self.file.flush()?;
+
+        let stamp = format!("{}.{}", self.path.display(), now.format("%Y%m%d-%H%M%S"));
+        // a busy log can fill up more than once a second
+        let mut rotated = PathBuf::from(&stamp);
+        let mut n = 1;
+        while rotated.exists() {
+            rotated = PathBuf::from(format!("{stamp}-{n}"));
+            n += 1;
+        }
+        std::fs::rename(&self.path, &rotated)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.file = BufWriter::new(file);
+        self.size = 0;
+
+        if let Err(err) = self.prune() {
+            tracing::warn!("failed removing old access logs: {}", err);
+        }
+        Ok(())
+    }
+
+    /// Removes the oldest rotated files past `keep`.
+    fn prune(&self) -> io::Result<()> {
+        if self.keep == 0 {
+            return Ok(());
+        }
+        let (Some(dir), Some(name)) = (self.path.parent(), self.path.file_name()) else {
+            return Ok(());
+        };
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        let prefix = format!("{}.", name.to_string_lossy());
+
+        let mut rotated = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                rotated.push(entry.path());
+            }
+        }
+        // the timestamp suffix sorts chronologically
+        rotated.sort();
+        let excess = rotated.len().saturating_sub(self.keep);
+        for path in &rotated[..excess] {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+fn writer(mut file: RotatingFile, rx: mpsc::Receiver<Message>) {
+    while let Ok(message) = rx.recv() {
+        let mut ack = None;
+        let mut next = Some(message);
+        // write whatever queued up, then flush once
+        while let Some(message) = next {
+            match message {
+                Message::Line(line) => {
+                    if let Err(err) = file.write_line(&line) {
+                        tracing::error!("failed writing access log: {}", err);
+                    }
+                }
+                Message::Flush(tx) => ack = Some(tx),
+            }
+            next = rx.try_recv().ok();
+        }
+
+        if let Err(err) = file.file.flush() {
+            tracing::error!("failed writing access log: {}", err);
+        }
+        if let Some(tx) = ack {
+            let _ = tx.send(());
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use std::path::Path;
+use std::time::SystemTime;
+
+use color_eyre::eyre::Result;
+
+use crate::cache::{brotli, gzip};
+
+/// Text-like assets worth serving precompressed. Everything else in `assets/`
+/// (webp, pdf, woff2, png) is already compressed.
+const PRECOMPRESS_EXTENSIONS: &[&str] = &["css", "svg", "txt", "xml", "js", "html"];
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Whether `path` is a `.br` or `.gz` sibling whose text asset is gone, which
+/// `ServeDir` would otherwise keep serving.
+fn is_orphan(path: &Path) -> bool {
+    let is_sibling = path
+        .extension()
+        .is_some_and(|ext| ext == "br" || ext == "gz");
+    let source = path.with_extension("");
+    let is_text = source
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|ext| PRECOMPRESS_EXTENSIONS.contains(&ext));
+    is_sibling && is_text && !source.exists()
+}
+
+/// Writes `.br` and `.gz` siblings next to every text asset under `dir`,
+/// skipping siblings that are already newer than their source, and removes
+/// those left behind by deleted assets. Returns the number of files written.
+pub fn precompress_assets(dir: &Path) -> Result<usize> {
+    let mut written = 0;
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            written += precompress_assets(&path)?;
+            continue;
+        }
+
+        if is_orphan(&path) {
+            std::fs::remove_file(&path)?;
+            tracing::info!("removed stale {}", path.display());
+            continue;
+        }
+
+        let Some(ext) = path.extension().and_then(|e| e.to_str()) else {
+            continue;
+        };
+        if !PRECOMPRESS_EXTENSIONS.contains(&ext) {
+            continue;
+        }
+
+        let source_modified = modified(&path);
+        let stale: Vec<_> = ["br", "gz"]
+            .into_iter()
+            .map(|suffix| (suffix, path.with_extension(format!("{ext}.{suffix}"))))
+            .filter(|(_, sibling)| modified(sibling).is_none_or(|m| Some(m) < source_modified))
+            .collect();
+
+        if stale.is_empty() {
+            continue;
+        }
+
+        let input = std::fs::read(&path)?;
+        for (suffix, sibling) in stale {
+            let encoded = match suffix {
+                "br" => brotli(&input, 11),
+                _ => gzip(&input),
+            };
+            std::fs::write(&sibling, encoded)?;
+            written += 1;
+        }
+    }
+
+    Ok(written)
+}
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+
+use maud::{html, Markup};
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+use crate::BlogPost;
+
+type Word = u32;
+
+/// Order-2 word-level Markov chain over the blog posts, used to generate
+/// endless plausible-looking pages for crawlers that ignore robots.txt.
+pub struct Babbler {
+    words: Vec<String>,
+    chain: HashMap<(Word, Word), Vec<Word>>,
+    starts: Vec<(Word, Word)>,
+}
+
+/// Strips the markdown down to running prose: code blocks, tables, math and
+/// links targets go, so the chain only learns sentences.
+fn prose(markdown: &str) -> String {
+    let mut out = String::with_capacity(markdown.len());
+    let mut in_code = false;
+    let mut in_math = false;
+
+    for line in markdown.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") {
+            in_code = !in_code;
+            continue;
+        }
+        if trimmed.starts_with("$$") {
+            // a single line block opens and closes on the same line
+            if trimmed.len() == 2 || !trimmed.ends_with("$$") {
+                in_math = !in_math;
+            }
+            continue;
+        }
+        if in_code || in_math || trimmed.starts_with('|') || trimmed.starts_with('<') {
+            continue;
+        }
+
+        for word in trimmed.split_whitespace() {
+            if word.contains("://") || word.contains('$') || word.starts_with("](") {
+                continue;
+            }
+            let word: String = word
+                .chars()
+                .filter(|c| !matches!(c, '#' | '*' | '_' | '`' | '[' | ']' | '(' | ')' | '>' | '<'))
+                .collect();
+            if !word.is_empty() {
+                out.push_str(&word);
+                out.push(' ');
+            }
+        }
+        out.push('\n');
+    }
+
+    out
+}
+
+fn ends_sentence(word: &str) -> bool {
+    word.ends_with(['.', '!', '?'])
+}
+
+impl Babbler {
+    pub fn train(blogposts: &[BlogPost]) -> Self {
+        let mut words: Vec<String> = Vec::new();
+        let mut ids: HashMap<String, Word> = HashMap::new();
+        let mut chain: HashMap<(Word, Word), Vec<Word>> = HashMap::new();
+        let mut starts = Vec::new();
+
+        for post in blogposts {
+            let text = prose(&post.markdown);
+            let tokens: Vec<Word> = text
+                .split_whitespace()
+                .map(|w| {
+                    *ids.entry(w.to_string()).or_insert_with(|| {
+                        words.push(w.to_string());
+                        (words.len() - 1) as Word
+                    })
+                })
+                .collect();
+
+            for (i, window) in tokens.windows(3).enumerate() {
+                chain
+                    .entry((window[0], window[1]))
+                    .or_default()
+                    .push(window[2]);
+
+                let starts_sentence = i == 0 || ends_sentence(&words[tokens[i - 1] as usize]);
+                let capitalised = words[window[0] as usize].starts_with(|c: char| c.is_uppercase());
+                if starts_sentence && capitalised {
+                    starts.push((window[0], window[1]));
+                }
+            }
+        }
+
+        Self {
+            words,
+            chain,
+            starts,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.starts.is_empty()
+    }
+
+    fn sentence(&self, rng: &mut StdRng) -> String {
+        let Some(&(mut a, mut b)) = self.starts.choose(rng) else {
+            return String::new();
+        };
+
+        let mut sentence = vec![
+            self.words[a as usize].as_str(),
+            self.words[b as usize].as_str(),
+        ];
+        while sentence.len() < 40 && !ends_sentence(sentence[sentence.len() - 1]) {
+            let Some(&next) = self.chain.get(&(a, b)).and_then(|next| next.choose(rng)) else {
+                break;
+            };
+            sentence.push(&self.words[next as usize]);
+            (a, b) = (b, next);
+        }
+
+        let mut sentence = sentence.join(" ");
+        if !ends_sentence(&sentence) {
+            sentence.push('.');
+        }
+        sentence
+    }
+
+    fn slug(&self, rng: &mut StdRng) -> String {
+        let len = rng.random_range(2..=4);
+        let mut parts = Vec::with_capacity(len);
+        while parts.len() < len {
+            let Some(word) = self.words.choose(rng) else {
+                break;
+            };
+            let word: String = word
+                .chars()
+                .filter(|c| c.is_ascii_alphanumeric())
+                .collect::<String>()
+                .to_ascii_lowercase();
+            if word.len() > 2 {
+                parts.push(word);
+            }
+        }
+        parts.join("-")
+    }
+
+    /// A seeded generator for the page at `path`, so the same url always
+    /// renders the same page.
+    pub fn rng(path: &str) -> StdRng {
+        let hash = blake3::hash(path.as_bytes());
+        StdRng::from_seed(*hash.as_bytes())
+    }
+
+    pub fn head(&self, rng: &mut StdRng) -> Markup {
+        let title = self.sentence(rng);
+        let title = title.trim_end_matches(['.', '!', '?']);
+
+        html! {
+            (maud::DOCTYPE)
+            meta charset="UTF-8";
+            meta name="robots" content="noindex";
+            title { (title) }
+            h1 { (title) }
+        }
+    }
+
+    /// One paragraph, with roughly every other one linking deeper.
+    pub fn paragraph(&self, rng: &mut StdRng) -> Markup {
+        let sentences: Vec<String> = (0..rng.random_range(3..=7))
+            .map(|_| self.sentence(rng))
+            .collect();
+        let link = rng.random_bool(0.6).then(|| {
+            let slug = self.slug(rng);
+            (format!("/babble/{slug}"), slug.replace('-', " "))
+        });
+
+        html! {
+            p {
+                (sentences.join(" "))
+                @if let Some((href, text)) = link {
+                    " " a href=(href) { (text) }
+                }
+            }
+        }
+    }
+}
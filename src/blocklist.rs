@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+
+use axum::{
+    extract::State,
+    http::{header, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Duration, Utc};
+use color_eyre::eyre::Result;
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+
+use crate::bots::Bot;
+use crate::config::BanAction;
+use crate::error::AppError;
+use crate::handlers::handle_babble;
+use crate::proxy::ClientInfo;
+use crate::SharedState;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Ban {
+    pub reason: String,
+    pub since: DateTime<Utc>,
+    pub until: DateTime<Utc>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Bans {
+    ips: HashMap<IpAddr, Ban>,
+    /// Keyed by the agent names from robots.txt, never by raw browser user
+    /// agents or generic signatures, which would ban everyone sharing them.
+    agents: HashMap<String, Ban>,
+}
+
+/// Snapshot of the active bans, for display.
+pub struct ActiveBans {
+    pub ips: Vec<(IpAddr, Ban)>,
+    pub agents: Vec<(String, Ban)>,
+}
+
+impl ActiveBans {
+    pub fn count(&self) -> usize {
+        self.ips.len() + self.agents.len()
+    }
+}
+
+impl Bans {
+    fn prune(&mut self, now: DateTime<Utc>) {
+        self.ips.retain(|_, ban| ban.until > now);
+        self.agents.retain(|_, ban| ban.until > now);
+    }
+}
+
+/// Time-limited bans for clients caught fetching paths robots.txt told them
+/// not to, persisted as JSON so they survive restarts.
+pub struct Blocklist {
+    path: PathBuf,
+    duration: Duration,
+    /// Never banned by address: a ban would hit everyone behind them.
+    exempt: Vec<IpNet>,
+    bans: RwLock<Bans>,
+    /// Bumped on every change, so a write can tell it's been overtaken.
+    version: Mutex<u64>,
+    /// The version last written, held while writing.
+    written: Arc<Mutex<u64>>,
+}
+
+impl Blocklist {
+    /// Loads the bans from `path`. Addresses in `exempt`, like trusted
+    /// proxies, and loopback, which unix socket peers show up as, are never
+    /// banned.
+    pub fn load(path: PathBuf, duration: Duration, exempt: Vec<IpNet>) -> Result<Self> {
+        let mut bans: Bans = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Bans::default(),
+            Err(err) => return Err(err.into()),
+        };
+        bans.prune(Utc::now());
+
+        Ok(Self {
+            path,
+            duration,
+            exempt,
+            bans: RwLock::new(bans),
+            version: Mutex::new(0),
+            written: Arc::new(Mutex::new(0)),
+        })
+    }
+
+    fn is_exempt(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        ip.is_loopback() || self.exempt.iter().any(|net| net.contains(&ip))
+    }
+
+    /// The active ban covering this client, if any.
+    pub fn check(&self, ip: IpAddr, bot: Option<&Bot>) -> Option<Ban> {
+        let now = Utc::now();
+        let bans = self.bans.read().unwrap_or_else(|e| e.into_inner());
+        let agent = bot.filter(|bot| bot.listed).map(|bot| bot.name.as_ref());
+        bans.ips
+            .get(&ip)
+            .or_else(|| agent.and_then(|agent| bans.agents.get(agent)))
+            .filter(|ban| ban.until > now)
+            .cloned()
+    }
+
+    /// Bans `ip`, unless it's exempt, and `bot` when it's an agent named in
+    /// robots.txt. Returns whether anything was banned.
+    pub fn ban(&self, ip: IpAddr, bot: Option<&Bot>, reason: String) -> bool {
+        let ip = (!self.is_exempt(ip)).then_some(ip);
+        let agent = bot.filter(|bot| bot.listed).map(|bot| bot.name.to_string());
+        if ip.is_none() && agent.is_none() {
+            return false;
+        }
+
+        let now = Utc::now();
+        let ban = Ban {
+            reason,
+            since: now,
+            until: now + self.duration,
+        };
+        self.update(|bans| {
+            if let Some(agent) = agent {
+                bans.agents.insert(agent, ban.clone());
+            }
+            if let Some(ip) = ip {
+                bans.ips.insert(ip, ban);
+            }
+        });
+        true
+    }
+
+    pub fn unban_ip(&self, ip: IpAddr) {
+        self.update(|bans| {
+            bans.ips.remove(&ip);
+        });
+    }
+
+    pub fn unban_agent(&self, agent: &str) {
+        self.update(|bans| {
+            bans.agents.remove(agent);
+        });
+    }
+
+    /// Active bans, soonest to expire first.
+    pub fn list(&self) -> ActiveBans {
+        let now = Utc::now();
+        let bans = self.bans.read().unwrap_or_else(|e| e.into_inner());
+
+        let mut ips: Vec<_> = bans
+            .ips
+            .iter()
+            .filter(|(_, ban)| ban.until > now)
+            .map(|(ip, ban)| (*ip, ban.clone()))
+            .collect();
+        let mut agents: Vec<_> = bans
+            .agents
+            .iter()
+            .filter(|(_, ban)| ban.until > now)
+            .map(|(agent, ban)| (agent.clone(), ban.clone()))
+            .collect();
+        ips.sort_by_key(|(_, ban)| ban.until);
+        agents.sort_by_key(|(_, ban)| ban.until);
+
+        ActiveBans { ips, agents }
+    }
+
+    /// Applies `f`, then writes the list out in the background. Writes hold
+    /// a lock and skip snapshots older than the one last written, so the
+    /// file always ends up with the latest list.
+    fn update(&self, f: impl FnOnce(&mut Bans)) {
+        let (version, json) = {
+            let mut bans = self.bans.write().unwrap_or_else(|e| e.into_inner());
+            f(&mut bans);
+            bans.prune(Utc::now());
+            let mut version = self.version.lock().unwrap_or_else(|e| e.into_inner());
+            *version += 1;
+            (*version, serde_json::to_vec_pretty(&*bans))
+        };
+
+        let json = match json {
+            Ok(json) => json,
+            Err(err) => {
+                tracing::error!("failed serializing blocklist: {}", err);
+                return;
+            }
+        };
+
+        let path = self.path.clone();
+        let written = self.written.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut written = written.lock().unwrap_or_else(|e| e.into_inner());
+            if *written >= version {
+                return;
+            }
+            let tmp = path.with_extension("json.tmp");
+            match std::fs::write(&tmp, json).and_then(|_| std::fs::rename(&tmp, &path)) {
+                Ok(()) => *written = version,
+                Err(err) => tracing::error!("failed writing blocklist {}: {}", path.display(), err),
+            }
+        });
+    }
+}
+
+/// Middleware turning away banned clients, and banning clients that fetch a
+/// path robots.txt disallows for their user agent.
+pub async fn enforce<B>(
+    State(state): State<SharedState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    // admin pages are token protected anyway, and must stay reachable to undo
+    // a ban on yourself
+    if request.uri().path().starts_with("/admin/") {
+        return next.run(request).await;
+    }
+    let Some(client) = request.extensions().get::<ClientInfo>().cloned() else {
+        return next.run(request).await;
+    };
+    let user_agent = request
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|ua| ua.to_str().ok())
+        .unwrap_or("");
+    let bot = state.bots.classify(request.headers());
+
+    if state.blocklist.check(client.ip, bot.as_ref()).is_none() {
+        let path = request.uri().path();
+        if state.robots.is_allowed(user_agent, path) {
+            return next.run(request).await;
+        }
+
+        let name = bot.as_ref().map_or("browser", |bot| bot.name.as_ref());
+        let reason = format!("fetched disallowed {path}");
+        if state.blocklist.ban(client.ip, bot.as_ref(), reason) {
+            tracing::warn!(
+                "banning {} ({}) for fetching disallowed {}",
+                client.ip,
+                name,
+                path
+            );
+        } else {
+            tracing::warn!(
+                "{} ({}) fetched disallowed {}, not banning",
+                client.ip,
+                name,
+                path
+            );
+        }
+    }
+
+    match state.config.blocklist.action {
+        BanAction::Forbidden => AppError::Forbidden.into_response(),
+        BanAction::Tarpit => {
+            let uri = request.uri().clone();
+            handle_babble(State(state), uri).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::*;
+
+    fn blocklist(name: &str) -> Blocklist {
+        let path =
+            std::env::temp_dir().join(format!("site-blocklist-{}-{name}.json", std::process::id()));
+        let exempt = vec!["10.0.0.0/8".parse().unwrap()];
+        Blocklist::load(path, Duration::hours(1), exempt).unwrap()
+    }
+
+    fn bot(name: &'static str, listed: bool) -> Bot<'static> {
+        Bot {
+            name: Cow::Borrowed(name),
+            listed,
+        }
+    }
+
+    #[tokio::test]
+    async fn bans_only_listed_agents() {
+        let blocklist = blocklist("agents");
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let other: IpAddr = "203.0.113.8".parse().unwrap();
+
+        assert!(blocklist.ban(ip, Some(&bot("curl", false)), "test".into()));
+        assert!(blocklist.check(ip, None).is_some());
+        assert!(blocklist.check(other, Some(&bot("curl", false))).is_none());
+
+        assert!(blocklist.ban(ip, Some(&bot("GPTBot", true)), "test".into()));
+        assert!(blocklist.check(other, Some(&bot("GPTBot", true))).is_some());
+        assert_eq!(blocklist.list().agents.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn never_bans_loopback_or_exempt_addresses() {
+        let blocklist = blocklist("exempt");
+        for ip in ["127.0.0.1", "::1", "10.1.2.3", "::ffff:10.1.2.3"] {
+            let ip: IpAddr = ip.parse().unwrap();
+            assert!(!blocklist.ban(ip, None, "test".into()), "{ip}");
+            assert!(blocklist.check(ip, None).is_none());
+        }
+        assert_eq!(blocklist.list().count(), 0);
+    }
+}
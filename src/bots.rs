@@ -0,0 +1,200 @@
+use std::borrow::Cow;
+
+use axum::http::{header, HeaderMap};
+
+use crate::robots::RobotsTxt;
+
+/// Substrings of user agents that only crawlers, feed readers and scripted
+/// clients send, on top of the agents named in robots.txt. Too generic to
+/// name a bot by, which is done by the product token they turn up in.
+const SIGNATURES: &[&str] = &[
+    "bot",
+    "crawler",
+    "spider",
+    "slurp",
+    "scrapy",
+    "feed",
+    "rss",
+    "fetcher",
+    "preview",
+    "headless",
+    "lighthouse",
+    "facebookexternalhit",
+    "curl",
+    "wget",
+    "httpie",
+    "python-requests",
+    "python-urllib",
+    "aiohttp",
+    "go-http-client",
+    "okhttp",
+    "java/",
+    "libwww-perl",
+    "node-fetch",
+    "axios",
+];
+
+/// Name used for requests without a user agent, browsers always send one.
+const NO_USER_AGENT: &str = "(no user agent)";
+
+/// Longest name recorded for a bot, user agents are made up by the client.
+const MAX_NAME_LEN: usize = 64;
+
+/// A client classified as a bot.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bot<'a> {
+    /// What its hits are tallied under: the agent named in robots.txt, or
+    /// the product token a signature was found in, e.g. `Googlebot`.
+    pub name: Cow<'a, str>,
+    /// Whether `name` is an agent named in robots.txt, rather than something
+    /// any number of unrelated clients may share.
+    pub listed: bool,
+}
+
+/// Tells crawlers and scripts apart from browsers by user agent.
+pub struct BotClassifier {
+    /// Lowercased names of the agents in robots.txt, paired with the names
+    /// as written.
+    agents: Vec<(String, String)>,
+}
+
+impl BotClassifier {
+    /// Seeds the classifier from the agents named in robots.txt, which are
+    /// checked before the generic [`SIGNATURES`].
+    pub fn new(robots: &RobotsTxt) -> Self {
+        let mut agents: Vec<(String, String)> = robots
+            .agents()
+            .map(|agent| (agent.to_ascii_lowercase(), agent.to_string()))
+            .collect();
+
+        // longer names first so "Applebot-Extended" wins over "Applebot"
+        agents.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then_with(|| a.0.cmp(&b.0)));
+        agents.dedup_by(|a, b| a.0 == b.0);
+
+        Self { agents }
+    }
+
+    /// Returns the matching bot, or `None` for browsers.
+    pub fn classify<'a>(&'a self, headers: &HeaderMap) -> Option<Bot<'a>> {
+        let Some(user_agent) = headers
+            .get(header::USER_AGENT)
+            .and_then(|ua| ua.to_str().ok())
+            .filter(|ua| !ua.trim().is_empty())
+        else {
+            return Some(Bot {
+                name: Cow::Borrowed(NO_USER_AGENT),
+                listed: false,
+            });
+        };
+
+        let lowercase = user_agent.to_ascii_lowercase();
+        if let Some((_, name)) = self
+            .agents
+            .iter()
+            .find(|(agent, _)| lowercase.contains(agent.as_str()))
+        {
+            return Some(Bot {
+                name: Cow::Borrowed(name),
+                listed: true,
+            });
+        }
+
+        SIGNATURES.iter().find_map(|signature| {
+            let at = lowercase.find(signature)?;
+            Some(Bot {
+                name: Cow::Owned(
+                    product_token(user_agent, at)
+                        .unwrap_or(signature)
+                        .to_string(),
+                ),
+                listed: false,
+            })
+        })
+    }
+}
+
+/// The product name in `user_agent` around byte `at`, without its version:
+/// `Googlebot` in `Mozilla/5.0 (compatible; Googlebot/2.1; ...)`.
+fn product_token(user_agent: &str, at: usize) -> Option<&str> {
+    let separator = |c: char| c.is_ascii_whitespace() || matches!(c, ';' | '(' | ')' | ',');
+    let start = user_agent[..at].rfind(separator).map_or(0, |i| i + 1);
+    let end = user_agent[at..]
+        .find(separator)
+        .map_or(user_agent.len(), |i| at + i);
+
+    // the part between slashes the signature starts in, so versions and the
+    // likes of "+http://example.com/bot.html" don't end up in the name
+    let token = &user_agent[start..end];
+    let offset = at - start;
+    let segment_start = token[..offset].rfind('/').map_or(0, |i| i + 1);
+    let segment_end = token[offset..]
+        .find('/')
+        .map_or(token.len(), |i| offset + i);
+    let name = token[segment_start..segment_end].trim_start_matches('+');
+
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return None;
+    }
+    Some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn classify(robots: &str, user_agent: Option<&str>) -> Option<(String, bool)> {
+        let classifier = BotClassifier::new(&RobotsTxt::parse(robots));
+        let mut headers = HeaderMap::new();
+        if let Some(user_agent) = user_agent {
+            headers.insert(
+                header::USER_AGENT,
+                HeaderValue::from_str(user_agent).unwrap(),
+            );
+        }
+        classifier
+            .classify(&headers)
+            .map(|bot| (bot.name.into_owned(), bot.listed))
+    }
+
+    #[test]
+    fn names_bots_by_product_token() {
+        let googlebot = "Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)";
+        assert_eq!(
+            classify("", Some(googlebot)),
+            Some(("Googlebot".into(), false))
+        );
+        assert_eq!(
+            classify("", Some("curl/8.5.0")),
+            Some(("curl".into(), false))
+        );
+        assert_eq!(
+            classify("", Some("Java/17.0.2")),
+            Some(("Java".into(), false))
+        );
+        assert_eq!(
+            classify(
+                "",
+                Some("Mozilla/5.0 (compatible; +https://example.com/bot.html)")
+            ),
+            Some(("bot.html".into(), false))
+        );
+    }
+
+    #[test]
+    fn prefers_listed_agents() {
+        let robots = "User-agent: Applebot\nUser-agent: Applebot-Extended\nDisallow: /\n";
+        assert_eq!(
+            classify(robots, Some("Mozilla/5.0 (Applebot-Extended/0.1)")),
+            Some(("Applebot-Extended".into(), true))
+        );
+    }
+
+    #[test]
+    fn browsers_and_missing_agents() {
+        let firefox = "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0";
+        assert_eq!(classify("", Some(firefox)), None);
+        assert_eq!(classify("", None), Some((NO_USER_AGENT.into(), false)));
+    }
+}
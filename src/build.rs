@@ -1,5 +1,38 @@
 use ructe::{Result, Ructe};
 
+/// Crates whose output ends up in rendered posts.
+const RENDERERS: &[&str] = &["comrak", "katex", "syntect"];
+
+/// The locked versions of [`RENDERERS`], e.g. `comrak 0.41.0`, so upgrading
+/// one misses the render cache instead of serving what the old one made.
+fn renderer_versions() -> String {
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    let lock = std::fs::read_to_string("Cargo.lock").unwrap_or_default();
+
+    let mut versions = Vec::new();
+    for package in lock.split("[[package]]") {
+        let field = |key: &str| {
+            package
+                .lines()
+                .find_map(|line| line.strip_prefix(key)?.trim().strip_prefix('='))
+                .map(|value| value.trim().trim_matches('"'))
+        };
+        if let (Some(name), Some(version)) = (field("name"), field("version")) {
+            if RENDERERS.contains(&name) {
+                versions.push(format!("{name} {version}"));
+            }
+        }
+    }
+    versions.sort();
+
+    if versions.is_empty() {
+        // without a lock file the crate version has to do
+        return "unlocked".to_string();
+    }
+    versions.join(", ")
+}
+
 fn main() -> Result<()> {
+    println!("cargo:rustc-env=RENDERER_VERSIONS={}", renderer_versions());
     Ructe::from_env()?.compile_templates("templates")
 }
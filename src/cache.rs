@@ -0,0 +1,420 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use axum::{
+    body,
+    http::{header, HeaderMap, HeaderValue, Response, StatusCode},
+    response::IntoResponse,
+};
+use bytes::Bytes;
+use flate2::{write::GzEncoder, Compression};
+use rayon::prelude::*;
+
+use crate::config::Config;
+use crate::fragments::current_year;
+use crate::handlers::{
+    render_404, render_ai_txt, render_blog, render_llms_txt, render_markdown, render_privacy,
+    render_robots, render_root, render_rss, render_sitemap, render_tag, VIEWS_MARKER,
+};
+use crate::security::InlineStyles;
+use crate::BlogPost;
+
+const HTML: &str = "text/html; charset=utf-8";
+const TEXT: &str = "text/plain; charset=utf-8";
+const MARKDOWN: &str = "text/markdown; charset=utf-8";
+const XML: &str = "application/xml";
+
+// Static pages are compressed once at load so we can afford the slowest
+// settings, post pages are re-encoded whenever their view count changes,
+// and only in the encodings clients ask for.
+const BROTLI_QUALITY_STATIC: u32 = 11;
+const BROTLI_QUALITY_DYNAMIC: u32 = 5;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Encoding {
+    Identity,
+    Gzip,
+    Brotli,
+}
+
+impl Encoding {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Identity => "identity",
+            Encoding::Gzip => "gzip",
+            Encoding::Brotli => "br",
+        }
+    }
+}
+
+/// Picks the best encoding we have a precompressed variant for, honouring
+/// q-values (including `q=0` opt-outs and the `*` wildcard).
+pub fn negotiate_encoding(headers: &HeaderMap) -> Encoding {
+    let Some(accept) = headers
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return Encoding::Identity;
+    };
+
+    let mut br = None;
+    let mut gzip = None;
+    let mut wildcard = None;
+
+    for part in accept.split(',') {
+        let mut params = part.split(';');
+        let coding = params.next().unwrap_or("").trim().to_ascii_lowercase();
+        let q = params
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        match coding.as_str() {
+            "br" => br = Some(q),
+            "gzip" | "x-gzip" => gzip = Some(q),
+            "*" => wildcard = Some(q),
+            _ => {}
+        }
+    }
+
+    let br = br.or(wildcard).unwrap_or(0.0);
+    let gzip = gzip.or(wildcard).unwrap_or(0.0);
+
+    if br > 0.0 && br >= gzip {
+        Encoding::Brotli
+    } else if gzip > 0.0 {
+        Encoding::Gzip
+    } else {
+        Encoding::Identity
+    }
+}
+
+pub(crate) fn brotli(input: &[u8], quality: u32) -> Bytes {
+    let mut out = Vec::with_capacity(input.len() / 4);
+    {
+        let mut writer = brotli::CompressorWriter::new(&mut out, 4096, quality, 22);
+        writer
+            .write_all(input)
+            .expect("writing to a Vec cannot fail");
+    }
+    out.into()
+}
+
+pub(crate) fn gzip(input: &[u8]) -> Bytes {
+    let mut encoder = GzEncoder::new(Vec::with_capacity(input.len() / 3), Compression::best());
+    encoder
+        .write_all(input)
+        .expect("writing to a Vec cannot fail");
+    encoder
+        .finish()
+        .expect("writing to a Vec cannot fail")
+        .into()
+}
+
+/// A fully rendered response body along with its compressed variants.
+pub struct CachedPage {
+    status: StatusCode,
+    content_type: &'static str,
+    identity: Bytes,
+    /// Filled up front for static pages, on first use for post pages.
+    gzip: OnceLock<Bytes>,
+    brotli: OnceLock<Bytes>,
+    brotli_quality: u32,
+    /// For the Content-Security-Policy, found while the body is still plain.
+    styles: Option<Arc<InlineStyles>>,
+}
+
+impl CachedPage {
+    /// A page with both compressed variants ready.
+    fn new(status: StatusCode, content_type: &'static str, body: Bytes, quality: u32) -> Self {
+        let styles = (content_type == HTML)
+            .then(|| Arc::new(InlineStyles::scan(&String::from_utf8_lossy(&body))));
+
+        let page = Self::lazy(status, content_type, body, quality, styles);
+        page.body(Encoding::Gzip);
+        page.body(Encoding::Brotli);
+        page
+    }
+
+    /// A page compressed only once a client asks for an encoding.
+    fn lazy(
+        status: StatusCode,
+        content_type: &'static str,
+        body: Bytes,
+        quality: u32,
+        styles: Option<Arc<InlineStyles>>,
+    ) -> Self {
+        Self {
+            status,
+            content_type,
+            identity: body,
+            gzip: OnceLock::new(),
+            brotli: OnceLock::new(),
+            brotli_quality: quality,
+            styles,
+        }
+    }
+
+    fn body(&self, encoding: Encoding) -> Bytes {
+        match encoding {
+            Encoding::Identity => self.identity.clone(),
+            Encoding::Gzip => self.gzip.get_or_init(|| gzip(&self.identity)).clone(),
+            Encoding::Brotli => self
+                .brotli
+                .get_or_init(|| brotli(&self.identity, self.brotli_quality))
+                .clone(),
+        }
+    }
+
+    pub fn respond(&self, headers: &HeaderMap) -> Response<body::BoxBody> {
+        let encoding = negotiate_encoding(headers);
+        let body = self.body(encoding);
+
+        let mut response = (self.status, body).into_response();
+        if let Some(styles) = &self.styles {
+            response.extensions_mut().insert(styles.clone());
+        }
+        let response_headers = response.headers_mut();
+        response_headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(self.content_type),
+        );
+        response_headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+        if encoding != Encoding::Identity {
+            response_headers.insert(
+                header::CONTENT_ENCODING,
+                HeaderValue::from_static(encoding.as_str()),
+            );
+        }
+
+        response
+    }
+}
+
+/// A blog post page split around its view counter, so a changed count only
+/// means stitching three slices together instead of re-rendering the post.
+/// The result is compressed outside the lock, in the encoding asked for.
+pub struct PostPage {
+    pub post: BlogPost,
+    head: Bytes,
+    tail: Bytes,
+    /// The count is digits only, so the styles don't change with it.
+    styles: Arc<InlineStyles>,
+    rendered: Mutex<Option<(usize, Arc<CachedPage>)>>,
+}
+
+impl PostPage {
+    fn new(post: &BlogPost, year: i32) -> Self {
+        let html = render_blog(post, year).into_string();
+        let (head, tail) = html
+            .split_once(VIEWS_MARKER)
+            .expect("blog template contains the views marker");
+
+        Self {
+            post: post.clone(),
+            styles: Arc::new(InlineStyles::scan(&format!("{head}{tail}"))),
+            head: Bytes::copy_from_slice(head.as_bytes()),
+            tail: Bytes::copy_from_slice(tail.as_bytes()),
+            rendered: Mutex::new(None),
+        }
+    }
+
+    fn page(&self, views: usize) -> Arc<CachedPage> {
+        let mut rendered = self.rendered.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((cached_views, page)) = rendered.as_ref() {
+            if *cached_views == views {
+                return page.clone();
+            }
+        }
+
+        let count = views.to_string();
+        let mut body = Vec::with_capacity(self.head.len() + count.len() + self.tail.len());
+        body.extend_from_slice(&self.head);
+        body.extend_from_slice(count.as_bytes());
+        body.extend_from_slice(&self.tail);
+
+        let page = Arc::new(CachedPage::lazy(
+            StatusCode::OK,
+            HTML,
+            body.into(),
+            BROTLI_QUALITY_DYNAMIC,
+            Some(self.styles.clone()),
+        ));
+        *rendered = Some((views, page.clone()));
+        page
+    }
+
+    pub fn respond(&self, views: usize, headers: &HeaderMap) -> Response<body::BoxBody> {
+        self.page(views).respond(headers)
+    }
+}
+
+/// Every cacheable page, rendered once after the blog posts are loaded.
+pub struct PageCache {
+    root: CachedPage,
+    rss: CachedPage,
+    sitemap: CachedPage,
+    /// Parts of the sitemap past the protocol limits, `/sitemaps/1.xml` first.
+    sitemap_parts: Vec<CachedPage>,
+    not_found: CachedPage,
+    privacy: CachedPage,
+    robots: CachedPage,
+    ai_txt: CachedPage,
+    llms_txt: CachedPage,
+    tags: HashMap<String, CachedPage>,
+    posts: HashMap<String, PostPage>,
+    markdown: HashMap<String, CachedPage>,
+    /// The year in the footers.
+    year: i32,
+}
+
+impl PageCache {
+    pub fn build(blogposts: &[BlogPost], config: &Config) -> Self {
+        let page = |status, content_type, body: String| {
+            CachedPage::new(status, content_type, body.into(), BROTLI_QUALITY_STATIC)
+        };
+        let year = current_year();
+
+        let mut tags: HashMap<String, CachedPage> = HashMap::new();
+        for tag in blogposts.iter().flat_map(|p| &p.tags) {
+            if !tags.contains_key(tag) {
+                let html = render_tag(tag, blogposts, year).into_string();
+                tags.insert(tag.clone(), page(StatusCode::OK, HTML, html));
+            }
+        }
+
+        let posts = blogposts
+            .par_iter()
+            .map(|post| (post.url.clone(), PostPage::new(post, year)))
+            .collect();
+        let markdown = blogposts
+            .par_iter()
+            .map(|post| {
+                (
+                    post.url.clone(),
+                    page(StatusCode::OK, MARKDOWN, render_markdown(post)),
+                )
+            })
+            .collect();
+
+        let sitemap = render_sitemap(blogposts, config, true);
+        let sitemap_parts = sitemap
+            .parts
+            .into_iter()
+            .map(|part| page(StatusCode::OK, XML, part))
+            .collect();
+
+        Self {
+            root: page(
+                StatusCode::OK,
+                HTML,
+                render_root(blogposts, year).into_string(),
+            ),
+            rss: page(
+                StatusCode::OK,
+                "application/rss+xml",
+                render_rss(blogposts, &config.base_url),
+            ),
+            sitemap: page(StatusCode::OK, XML, sitemap.root),
+            sitemap_parts,
+            not_found: page(
+                StatusCode::NOT_FOUND,
+                HTML,
+                render_404(Some(""), &[], year).into_string(),
+            ),
+            privacy: page(
+                StatusCode::OK,
+                HTML,
+                render_privacy(&config.stats, year).into_string(),
+            ),
+            robots: page(StatusCode::OK, TEXT, render_robots(&config.crawlers)),
+            ai_txt: page(StatusCode::OK, TEXT, render_ai_txt(&config.crawlers)),
+            llms_txt: page(StatusCode::OK, TEXT, render_llms_txt(blogposts, config)),
+            tags,
+            posts,
+            markdown,
+            year,
+        }
+    }
+
+    /// The year the pages were rendered in, which their footers show. The
+    /// cache has to be built again once it's over.
+    pub fn year(&self) -> i32 {
+        self.year
+    }
+
+    pub fn root(&self) -> &CachedPage {
+        &self.root
+    }
+
+    pub fn rss(&self) -> &CachedPage {
+        &self.rss
+    }
+
+    pub fn sitemap(&self) -> &CachedPage {
+        &self.sitemap
+    }
+
+    /// Part `n` of a split sitemap, counting from 1.
+    pub fn sitemap_part(&self, n: usize) -> Option<&CachedPage> {
+        self.sitemap_parts.get(n.checked_sub(1)?)
+    }
+
+    pub fn not_found(&self) -> &CachedPage {
+        &self.not_found
+    }
+
+    pub fn privacy(&self) -> &CachedPage {
+        &self.privacy
+    }
+
+    pub fn robots(&self) -> &CachedPage {
+        &self.robots
+    }
+
+    pub fn ai_txt(&self) -> &CachedPage {
+        &self.ai_txt
+    }
+
+    pub fn llms_txt(&self) -> &CachedPage {
+        &self.llms_txt
+    }
+
+    pub fn tag(&self, tag: &str) -> Option<&CachedPage> {
+        self.tags.get(tag)
+    }
+
+    pub fn post(&self, url: &str) -> Option<&PostPage> {
+        self.posts.get(url)
+    }
+
+    /// The markdown source of a post, for `/blog/{url}.md`.
+    pub fn markdown(&self, url: &str) -> Option<&CachedPage> {
+        self.markdown.get(url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn negotiate(accept: &str) -> Encoding {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_ENCODING, accept.parse().unwrap());
+        negotiate_encoding(&headers)
+    }
+
+    #[test]
+    fn q_zero_opts_out_of_an_encoding() {
+        assert_eq!(negotiate("gzip, deflate, br"), Encoding::Brotli);
+        assert_eq!(negotiate("br;q=0, gzip"), Encoding::Gzip);
+        assert_eq!(negotiate("br;q=0.0, gzip;q=0"), Encoding::Identity);
+        assert_eq!(negotiate("*;q=0"), Encoding::Identity);
+        assert_eq!(negotiate("*, br;q=0"), Encoding::Gzip);
+        assert_eq!(negotiate("gzip;q=0, *"), Encoding::Brotli);
+        assert_eq!(negotiate("br;q=0.5, gzip;q=0.8"), Encoding::Gzip);
+        assert_eq!(negotiate("identity"), Encoding::Identity);
+        assert_eq!(negotiate_encoding(&HeaderMap::new()), Encoding::Identity);
+    }
+}
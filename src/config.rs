@@ -0,0 +1,414 @@
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Result};
+use ipnet::IpNet;
+use serde::Deserialize;
+
+/// Site configuration, read from `config.yaml` in the site root (or the file
+/// named by `SITE_CONFIG`). Every field has a default, so the file is optional.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// `host:port` or `unix:/path/to/socket`. Ignored when systemd passes a
+    /// socket in through socket activation.
+    pub listen: String,
+    /// How long in-flight requests get to finish after SIGTERM or SIGINT.
+    pub drain_timeout_secs: u64,
+    /// Serves https on `listen` when set.
+    pub tls: Option<TlsConfig>,
+    /// Where mutable state such as the stats database lives. Kept apart from
+    /// the site root, which is read-only in the nix store.
+    pub data_dir: PathBuf,
+    /// Scheme and host the site is served from, for absolute links.
+    pub base_url: String,
+    /// YAML map of legacy paths to where they moved, relative to the site
+    /// root. Optional.
+    pub redirects: PathBuf,
+    /// Reverse proxies (as CIDRs) allowed to set `Forwarded`,
+    /// `X-Forwarded-For` and `X-Real-IP`.
+    pub trusted_proxies: Vec<IpNet>,
+    /// Unlocks the `/admin` pages when set, entered at `/admin/login` or sent
+    /// as `Authorization: Bearer`.
+    pub admin_token: Option<String>,
+    pub stats: StatsConfig,
+    pub babble: BabbleConfig,
+    pub blocklist: BlocklistConfig,
+    pub crawlers: CrawlerPolicy,
+    pub rate_limit: RateLimitConfig,
+    pub security: SecurityConfig,
+    pub ops: OpsConfig,
+    pub log: LogConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StatsConfig {
+    /// Raw view records and daily referrer and entry page tallies older than
+    /// this are deleted, daily view totals are kept.
+    pub retention_days: u32,
+    /// Daily bot hit tallies older than this are deleted. The dashboard only
+    /// shows the last 30 days and 12 weeks of them.
+    pub bot_hits_retention_days: u32,
+    /// Maximum number of views buffered before they are written to disk.
+    pub batch_size: usize,
+    /// Maximum time a view can sit in the buffer before it is written.
+    pub flush_interval_secs: u64,
+}
+
+/// The `/babble/` crawler tarpit.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BabbleConfig {
+    /// Paragraphs per page, each one preceded by `chunk_delay_ms`.
+    pub paragraphs: usize,
+    pub chunk_delay_ms: u64,
+    /// Connections held open at once, anyone past that gets a quick 503.
+    pub max_connections: usize,
+}
+
+/// What banned clients get.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BanAction {
+    Forbidden,
+    Tarpit,
+}
+
+/// Bans for clients that fetch paths robots.txt disallows for them.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BlocklistConfig {
+    pub ban_hours: u32,
+    pub action: BanAction,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// PEM certificate chain, leaf first.
+    pub cert: PathBuf,
+    /// PEM private key (PKCS#8, PKCS#1 or SEC1).
+    pub key: PathBuf,
+    /// How often the files are checked for changes, so renewed certificates
+    /// apply without a restart.
+    pub reload_interval_secs: u64,
+    /// A plain http listener redirecting everything to https, e.g. `0.0.0.0:80`.
+    pub redirect_http: Option<String>,
+}
+
+/// How log lines are written.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    /// Format of the application log on stdout.
+    pub format: LogFormat,
+    /// Writes an access log when set.
+    pub access: Option<AccessLogConfig>,
+    /// Exports spans to an OpenTelemetry collector when set.
+    pub otlp: Option<OtlpConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct OtlpConfig {
+    /// Collector traces endpoint, spoken to in OTLP over http/protobuf.
+    pub endpoint: String,
+    pub service_name: String,
+    /// Share of new traces sampled, from 0 to 1. Requests carrying a
+    /// `traceparent` follow the caller's decision.
+    pub sample_ratio: f64,
+}
+
+/// Format of access log lines.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+    /// Combined Log Format, with the latency in seconds appended.
+    Combined,
+    Json,
+}
+
+/// When the access log starts a new file, regardless of size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    Never,
+    Hourly,
+    Daily,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AccessLogConfig {
+    /// Relative paths are resolved against `data_dir`.
+    pub path: PathBuf,
+    pub format: AccessLogFormat,
+    pub rotation: Rotation,
+    /// Starts a new file once the current one reaches this size, 0 for no
+    /// limit.
+    pub max_size_mb: u64,
+    /// Rotated files kept around, 0 keeps them all.
+    pub keep: usize,
+}
+
+/// Who may reach `/healthz`, `/readyz` and `/metrics`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct OpsConfig {
+    /// Networks (as CIDRs) let in without a token.
+    pub allow: Vec<IpNet>,
+    /// Lets anyone in who sends it as `Authorization: Bearer`.
+    pub token: Option<String>,
+}
+
+/// Which crawlers may fetch what. `/robots.txt`, `/ai.txt` and `/llms.txt`
+/// are generated from this, and the blocklist enforces it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CrawlerPolicy {
+    pub groups: Vec<AgentGroup>,
+    pub sitemap: Option<String>,
+    /// Whether ai.txt permits training models on the site's content.
+    pub ai_training: bool,
+}
+
+/// One robots.txt group: rules shared by a set of user agents.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AgentGroup {
+    pub agents: Vec<String>,
+    pub allow: Vec<String>,
+    pub disallow: Vec<String>,
+}
+
+/// Per client IP token buckets, with separate budgets for pages, feeds and
+/// assets.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    /// Clients (as CIDRs) that are never limited.
+    pub allowlist: Vec<IpNet>,
+    pub pages: Limit,
+    /// `/rss.xml`, `/sitemap.xml`, `/robots.txt`, `/ai.txt` and `/llms.txt`.
+    pub feeds: Limit,
+    pub assets: Limit,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Limit {
+    /// Sustained requests per minute, 0 for no limit.
+    pub per_minute: u32,
+    /// Requests allowed in a burst on top of that. A burst of 0 is taken
+    /// as 1, so requests still get through at the sustained rate.
+    pub burst: u32,
+}
+
+/// Security headers added to every response. Empty strings leave a header
+/// out.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SecurityConfig {
+    /// Base Content-Security-Policy for HTML pages. `style-src` is appended
+    /// per page, allowing the hashes of the inline styles it contains.
+    pub content_security_policy: String,
+    /// `Strict-Transport-Security` max-age, only sent over https, 0 for none.
+    pub hsts_max_age: u64,
+    pub hsts_include_subdomains: bool,
+    pub hsts_preload: bool,
+    pub referrer_policy: String,
+    pub permissions_policy: String,
+    pub frame_options: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: "0.0.0.0:8080".to_string(),
+            drain_timeout_secs: 30,
+            tls: None,
+            data_dir: PathBuf::from("data"),
+            redirects: PathBuf::from("redirects.yaml"),
+            base_url: "https://bergsoe.net".to_string(),
+            trusted_proxies: Vec::new(),
+            stats: StatsConfig::default(),
+            admin_token: None,
+            babble: BabbleConfig::default(),
+            blocklist: BlocklistConfig::default(),
+            crawlers: CrawlerPolicy::default(),
+            rate_limit: RateLimitConfig::default(),
+            security: SecurityConfig::default(),
+            ops: OpsConfig::default(),
+            log: LogConfig::default(),
+        }
+    }
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        Self {
+            retention_days: 90,
+            bot_hits_retention_days: 90,
+            batch_size: 64,
+            flush_interval_secs: 5,
+        }
+    }
+}
+
+impl Config {
+    /// Where rendered posts are cached between restarts.
+    pub fn render_cache_dir(&self) -> PathBuf {
+        self.data_dir.join("render-cache")
+    }
+
+    /// Where the config is read from.
+    pub fn path(path_prefix: &Path) -> PathBuf {
+        match std::env::var("SITE_CONFIG") {
+            Ok(path) => PathBuf::from(path),
+            Err(_) => path_prefix.join("config.yaml"),
+        }
+    }
+
+    /// Loads the config, or the defaults when there is none. Runs before
+    /// logging is set up, since the config decides how to log.
+    pub fn load(path_prefix: &Path) -> Result<Self> {
+        let path = Self::path(path_prefix);
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        let text = std::fs::read_to_string(&path)?;
+        match serde_yaml::from_str(&text) {
+            Ok(config) => Ok(config),
+            Err(err) => Err(eyre!(format!(
+                "Error parsing config ({}): {err}",
+                path.display()
+            ))),
+        }
+    }
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("access.log"),
+            format: AccessLogFormat::Combined,
+            rotation: Rotation::Daily,
+            max_size_mb: 100,
+            keep: 14,
+        }
+    }
+}
+
+impl Default for OtlpConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: "http://localhost:4318/v1/traces".to_string(),
+            service_name: "site".to_string(),
+            sample_ratio: 1.0,
+        }
+    }
+}
+
+impl Default for OpsConfig {
+    fn default() -> Self {
+        Self {
+            allow: vec![
+                "127.0.0.0/8".parse().expect("valid cidr"),
+                "::1/128".parse().expect("valid cidr"),
+            ],
+            token: None,
+        }
+    }
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            cert: PathBuf::from("cert.pem"),
+            key: PathBuf::from("key.pem"),
+            reload_interval_secs: 60,
+            redirect_http: None,
+        }
+    }
+}
+
+impl Default for BabbleConfig {
+    fn default() -> Self {
+        Self {
+            paragraphs: 12,
+            chunk_delay_ms: 2000,
+            max_connections: 32,
+        }
+    }
+}
+
+impl Default for BlocklistConfig {
+    fn default() -> Self {
+        Self {
+            ban_hours: 24,
+            action: BanAction::Tarpit,
+        }
+    }
+}
+
+impl Default for CrawlerPolicy {
+    /// Everyone may crawl everything but the tarpit.
+    fn default() -> Self {
+        Self {
+            groups: vec![AgentGroup {
+                agents: vec!["*".to_string()],
+                allow: vec!["/".to_string()],
+                disallow: vec!["/babble/".to_string()],
+            }],
+            sitemap: None,
+            ai_training: false,
+        }
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            allowlist: Vec::new(),
+            pages: Limit {
+                per_minute: 60,
+                burst: 30,
+            },
+            feeds: Limit {
+                per_minute: 10,
+                burst: 10,
+            },
+            assets: Limit {
+                per_minute: 600,
+                burst: 200,
+            },
+        }
+    }
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            content_security_policy: "default-src 'self'; img-src 'self' data:; object-src 'none'; \
+                                      base-uri 'self'; form-action 'self'; frame-ancestors 'none'"
+                .to_string(),
+            hsts_max_age: 31_536_000,
+            hsts_include_subdomains: false,
+            hsts_preload: false,
+            referrer_policy: "strict-origin-when-cross-origin".to_string(),
+            permissions_policy: "camera=(), microphone=(), geolocation=(), interest-cohort=()"
+                .to_string(),
+            frame_options: "DENY".to_string(),
+        }
+    }
+}
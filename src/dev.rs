@@ -0,0 +1,283 @@
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use axum::{
+    body::{self, Body},
+    extract::State,
+    http::{header, HeaderValue, Request},
+    middleware::Next,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+};
+use futures_util::{stream, StreamExt};
+use tokio::sync::broadcast;
+
+use crate::error::AppError;
+use crate::site::SiteHandle;
+use crate::SharedState;
+
+/// How often the watched files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+
+pub const EVENTS_PATH: &str = "/_dev/events";
+pub const SCRIPT_PATH: &str = "/_dev/reload.js";
+
+/// Reloads open pages when the content changes and shows them what failed
+/// to render. An external script rather than an inline one, so the
+/// Content-Security-Policy doesn't need loosening.
+const SCRIPT: &str = r##"(() => {
+  const events = new EventSource("/_dev/events");
+  events.addEventListener("reload", () => location.reload());
+  events.addEventListener("problems", (event) => {
+    const problems = JSON.parse(event.data);
+    document.getElementById("dev-overlay")?.remove();
+    if (problems.length === 0) return;
+
+    const overlay = document.createElement("div");
+    overlay.id = "dev-overlay";
+    Object.assign(overlay.style, {
+      position: "fixed", inset: "0", zIndex: "2147483647", overflow: "auto",
+      padding: "2rem", background: "rgba(20, 20, 20, 0.92)", color: "#f4f4f4",
+      font: "14px/1.5 monospace",
+    });
+    const heading = document.createElement("h2");
+    heading.textContent = `${problems.length} problem(s) loading content`;
+    heading.style.color = "#ff6b6b";
+    overlay.append(heading);
+    for (const problem of problems) {
+      const pre = document.createElement("pre");
+      pre.textContent = problem;
+      pre.style.whiteSpace = "pre-wrap";
+      overlay.append(pre);
+    }
+    const close = document.createElement("button");
+    close.textContent = "dismiss";
+    close.addEventListener("click", () => overlay.remove());
+    overlay.append(close);
+    document.body.append(overlay);
+  });
+})();
+"##;
+
+/// Live-reload state of a site served with `--dev`: what currently fails to
+/// load, and the pages listening for changes.
+pub struct LiveReload {
+    problems: Mutex<Vec<String>>,
+    events: broadcast::Sender<Signal>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Signal {
+    Reload,
+    /// The server is shutting down, ends the event streams.
+    Close,
+}
+
+impl Default for LiveReload {
+    fn default() -> Self {
+        Self {
+            problems: Mutex::new(Vec::new()),
+            events: broadcast::channel(16).0,
+        }
+    }
+}
+
+impl LiveReload {
+    fn problems(&self) -> Vec<String> {
+        self.problems
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// What failed to load, shown on every page until it's fixed.
+    pub fn set_problems(&self, problems: Vec<String>) {
+        *self.problems.lock().unwrap_or_else(|e| e.into_inner()) = problems;
+    }
+
+    /// Tells every open page to reload.
+    pub fn reload(&self) {
+        let _ = self.events.send(Signal::Reload);
+    }
+
+    /// Ends every event stream, so shutting down doesn't wait on them.
+    pub fn close(&self) {
+        let _ = self.events.send(Signal::Close);
+    }
+}
+
+/// `/_dev/events`, a stream of server-sent events for the reload script.
+/// Starts with the current problems, then says `reload` on every change.
+pub async fn handle_events(State(state): State<SharedState>) -> Response {
+    let Some(live) = &state.dev else {
+        return AppError::NotFound.into_response();
+    };
+
+    let problems = Event::default()
+        .event("problems")
+        .json_data(live.problems())
+        .expect("serializing strings can't fail");
+    let changes = stream::unfold(live.events.subscribe(), |mut events| async move {
+        loop {
+            match events.recv().await {
+                Ok(Signal::Reload) => {
+                    return Some((Event::default().event("reload").data(""), events));
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Ok(Signal::Close) | Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+    let events = stream::once(async { problems })
+        .chain(changes)
+        .map(Ok::<_, Infallible>);
+
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+pub async fn handle_script() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/javascript; charset=utf-8")],
+        SCRIPT,
+    )
+}
+
+/// Middleware adding the reload script to HTML pages. Responses are asked
+/// for unencoded so the script can be spliced in, and marked `no-cache`, so
+/// a reload picks up changed assets too.
+pub async fn inject<B>(mut request: Request<B>, next: Next<B>) -> Response {
+    request.headers_mut().remove(header::ACCEPT_ENCODING);
+    let mut response = next.run(request).await;
+    response
+        .headers_mut()
+        .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+
+    let is_html = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/html"));
+    if !is_html || response.headers().contains_key(header::CONTENT_ENCODING) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            return AppError::Internal(format!("failed reading response body: {err}"))
+                .into_response();
+        }
+    };
+    let html = String::from_utf8_lossy(&bytes);
+    let tag = format!("<script src=\"{SCRIPT_PATH}\" defer></script>");
+    let html = match html.rfind("</body>") {
+        Some(at) => format!("{}{tag}{}", &html[..at], &html[at..]),
+        None => format!("{html}{tag}"),
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, body::boxed(Body::from(html)))
+}
+
+/// Modification time and size of every watched file.
+type Snapshot = BTreeMap<PathBuf, (Option<SystemTime>, u64)>;
+
+fn scan(path: &Path, snapshot: &mut Snapshot) {
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return;
+    };
+    if metadata.is_file() {
+        snapshot.insert(
+            path.to_path_buf(),
+            (metadata.modified().ok(), metadata.len()),
+        );
+        return;
+    }
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        // editor swap and backup files, and the precompressed siblings we write
+        if name.starts_with('.')
+            || name.ends_with('~')
+            || name.ends_with(".br")
+            || name.ends_with(".gz")
+        {
+            continue;
+        }
+        scan(&path, snapshot);
+    }
+}
+
+fn snapshot(paths: &[PathBuf]) -> Snapshot {
+    let mut snapshot = Snapshot::new();
+    for path in paths {
+        scan(path, &mut snapshot);
+    }
+    snapshot
+}
+
+/// Paths that were added, removed or changed between two snapshots.
+fn changes(before: &Snapshot, after: &Snapshot) -> Vec<PathBuf> {
+    let removed = before.keys().filter(|path| !after.contains_key(*path));
+    let changed = after
+        .iter()
+        .filter(|(path, stamp)| before.get(*path) != Some(*stamp))
+        .map(|(path, _)| path);
+    removed.chain(changed).cloned().collect()
+}
+
+/// Polls `assets` and the `content` files for changes, reloading the
+/// content when one of the latter changed, then the open pages.
+pub fn watch(site: SiteHandle, live: Arc<LiveReload>, content: Vec<PathBuf>, assets: PathBuf) {
+    let mut watched = content.clone();
+    watched.push(assets);
+    let scan_all = move || {
+        let watched = watched.clone();
+        async move {
+            tokio::task::spawn_blocking(move || snapshot(&watched))
+                .await
+                .unwrap_or_default()
+        }
+    };
+
+    tokio::spawn(async move {
+        let mut before = scan_all().await;
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let after = scan_all().await;
+            let changed = changes(&before, &after);
+            before = after;
+            if changed.is_empty() {
+                continue;
+            }
+
+            for path in &changed {
+                tracing::info!("changed: {}", path.display());
+            }
+            let content_changed = changed
+                .iter()
+                .any(|path| content.iter().any(|c| path.starts_with(c)));
+            if content_changed {
+                match site.reload().await {
+                    Ok(()) => live.set_problems(site.content().errors().to_vec()),
+                    Err(err) => {
+                        tracing::error!("reload failed: {}", err);
+                        live.set_problems(vec![err.to_string()]);
+                    }
+                }
+            }
+            live.reload();
+        }
+    });
+}
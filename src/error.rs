@@ -0,0 +1,188 @@
+use std::any::Any;
+use std::time::Duration;
+
+use axum::{
+    http::{header, HeaderMap, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use color_eyre::eyre::Report;
+use serde::Serialize;
+
+use crate::handlers::render_error;
+
+/// Errors handlers and middleware answer with. Each maps to a status and
+/// renders as a page with the site header and footer, which [`negotiate`]
+/// turns into JSON for clients that ask for it.
+#[derive(Debug)]
+pub enum AppError {
+    BadRequest(String),
+    Forbidden,
+    NotFound,
+    TooManyRequests {
+        retry_after: Duration,
+    },
+    /// Logged, but not shown to the client.
+    Internal(String),
+    Unavailable {
+        retry_after: Option<Duration>,
+    },
+}
+
+/// What an error response is about, kept as a response extension so
+/// [`negotiate`] can render it as JSON.
+#[derive(Clone, Debug, Serialize)]
+pub struct ErrorInfo {
+    pub status: u16,
+    pub message: String,
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Forbidden => StatusCode::FORBIDDEN,
+            AppError::NotFound => StatusCode::NOT_FOUND,
+            AppError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::Unavailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    /// The message shown to the client.
+    fn message(&self) -> String {
+        match self {
+            AppError::BadRequest(reason) => reason.clone(),
+            AppError::Forbidden => "You are not allowed to see this page.".to_string(),
+            AppError::NotFound => "There is nothing here.".to_string(),
+            AppError::TooManyRequests { .. } => "Too many requests, please slow down.".to_string(),
+            AppError::Internal(_) => "Something went wrong on our end.".to_string(),
+            AppError::Unavailable { .. } => {
+                "The server is busy, please try again later.".to_string()
+            }
+        }
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            AppError::TooManyRequests { retry_after } => Some(*retry_after),
+            AppError::Unavailable { retry_after } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// The error page, without logging anything.
+    fn render(self) -> Response {
+        let status = self.status();
+        let message = self.message();
+        let mut response = (status, render_error(status, &message)).into_response();
+        if let Some(retry_after) = self.retry_after() {
+            let seconds = (retry_after.as_secs_f64().ceil() as u64).max(1);
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        response.extensions_mut().insert(ErrorInfo {
+            status: status.as_u16(),
+            message,
+        });
+        response
+    }
+}
+
+impl From<Report> for AppError {
+    fn from(err: Report) -> Self {
+        AppError::Internal(err.to_string())
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        if let AppError::Internal(err) = &self {
+            tracing::error!("internal error: {}", err);
+        }
+        self.render()
+    }
+}
+
+/// Whether the client prefers JSON over HTML, going by its `Accept` header.
+pub fn wants_json(headers: &HeaderMap) -> bool {
+    let Some(accept) = headers.get(header::ACCEPT).and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+
+    let mut json = 0.0;
+    let mut html = 0.0;
+    for part in accept.split(',') {
+        let mut params = part.split(';');
+        let media = params.next().unwrap_or("").trim().to_ascii_lowercase();
+        let q = params
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        if media == "application/json" || media.ends_with("+json") {
+            json = f32::max(json, q);
+        } else if media == "text/html" || media == "application/xhtml+xml" {
+            html = f32::max(html, q);
+        }
+    }
+    json > 0.0 && json > html
+}
+
+/// Middleware answering clients that want JSON with a JSON error body, for
+/// error pages from [`AppError`] and any other HTML error page, such as the
+/// cached 404.
+pub async fn negotiate<B>(request: Request<B>, next: Next<B>) -> Response {
+    let json = wants_json(request.headers());
+    let response = next.run(request).await;
+    if !json || !(response.status().is_client_error() || response.status().is_server_error()) {
+        return response;
+    }
+
+    let info = match response.extensions().get::<ErrorInfo>() {
+        Some(info) => info.clone(),
+        None => {
+            let is_html = response
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|v| v.starts_with("text/html"));
+            if !is_html {
+                return response;
+            }
+            let status = response.status();
+            ErrorInfo {
+                status: status.as_u16(),
+                message: status.canonical_reason().unwrap_or("Error").to_string(),
+            }
+        }
+    };
+
+    let retry_after = response.headers().get(header::RETRY_AFTER).cloned();
+    let mut json = (
+        response.status(),
+        Json(serde_json::json!({ "error": info })),
+    )
+        .into_response();
+    if let Some(retry_after) = retry_after {
+        json.headers_mut().insert(header::RETRY_AFTER, retry_after);
+    }
+    json
+}
+
+/// Answers a panicked request with a 500 page instead of dropping the
+/// connection, for the catch-panic layer.
+pub fn panic_response(panic: Box<dyn Any + Send + 'static>) -> Response {
+    let message = if let Some(message) = panic.downcast_ref::<String>() {
+        message.as_str()
+    } else if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else {
+        "unknown panic"
+    };
+    tracing::error!("request handler panicked: {}", message);
+
+    AppError::Internal(message.to_string()).render()
+}
@@ -0,0 +1,240 @@
+use std::collections::BTreeSet;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::Datelike;
+use color_eyre::eyre::{eyre, Result};
+
+use crate::config::Config;
+use crate::fragments::current_year;
+use crate::handlers::{
+    render_404, render_ai_txt, render_blog, render_llms_txt, render_markdown, render_privacy,
+    render_robots, render_root, render_rss, render_sitemap, render_tag, VIEWS_MARKER,
+};
+use crate::metrics::Metrics;
+use crate::post;
+
+/// Left in the output directory, so a later export knows it may clear it.
+const MARKER: &str = ".site-export";
+
+/// Writes every page the server renders from the content as static files
+/// under `out`, and copies `assets/` next to them.
+///
+/// Pages land where a static host looks for them: `/blog/{url}` in
+/// `blog/{url}/index.html`, the 404 page in `404.html`. Root-relative links
+/// get `base_path` put in front, for sites hosted below the domain root.
+/// Nothing that changes between runs ends up in the output, so exporting
+/// unchanged content gives the same files: view counts are left out,
+/// `lastmod` ignores file modification times and the copyright year is the
+/// year of the newest post. The 404 page has no search form, there's no
+/// server to search. Returns the number of files written.
+pub async fn export(root: &Path, config: &Config, out: &Path, base_path: &str) -> Result<usize> {
+    let base_path = match base_path.trim_matches('/') {
+        "" => String::new(),
+        path => format!("/{path}"),
+    };
+    let mut config = config.clone();
+    config.base_url = format!("{}{base_path}", config.base_url.trim_end_matches('/'));
+
+    let (mut blogposts, content_errors) = post::load_posts(
+        &root.join("blog"),
+        Arc::new(post::render_cache(config.render_cache_dir())),
+        &Metrics::default(),
+    )
+    .await?;
+    if !content_errors.is_empty() {
+        return Err(eyre!(format!(
+            "Error loading content: {}",
+            content_errors.join(", ")
+        )));
+    }
+    blogposts.retain(|post| post.is_published());
+    // mtimes depend on the checkout, not the content
+    for post in &mut blogposts {
+        post.modified = None;
+    }
+
+    let year = blogposts
+        .iter()
+        .map(|post| post.date.year())
+        .max()
+        .unwrap_or_else(current_year);
+
+    prepare(out)?;
+    let mut files = Files {
+        out: out.to_path_buf(),
+        base_path,
+        written: 0,
+    };
+
+    files.html("index.html", &render_root(&blogposts, year).into_string())?;
+    files.html("404.html", &render_404(None, &[], year).into_string())?;
+    files.html(
+        "privacy/index.html",
+        &render_privacy(&config.stats, year).into_string(),
+    )?;
+
+    for post in &blogposts {
+        if !is_safe(&post.url) {
+            tracing::warn!("not exporting post with unsafe url: {}", post.url);
+            continue;
+        }
+        files.html(
+            &format!("blog/{}/index.html", post.url),
+            &without_views(&render_blog(post, year).into_string()),
+        )?;
+        files.write(&format!("blog/{}.md", post.url), &render_markdown(post))?;
+    }
+
+    // tag pages only list posts that aren't archived
+    let tags: BTreeSet<&String> = blogposts
+        .iter()
+        .filter(|p| !p.archived)
+        .flat_map(|p| &p.tags)
+        .collect();
+    for tag in tags {
+        if !is_safe(tag) {
+            tracing::warn!("not exporting tag with unsafe name: {}", tag);
+            continue;
+        }
+        files.html(
+            &format!("tag/{tag}/index.html"),
+            &render_tag(tag, &blogposts, year).into_string(),
+        )?;
+    }
+
+    files.write("rss.xml", &render_rss(&blogposts, &config.base_url))?;
+    let sitemap = render_sitemap(&blogposts, &config, false);
+    files.write("sitemap.xml", &sitemap.root)?;
+    for (n, part) in sitemap.parts.iter().enumerate() {
+        files.write(&format!("sitemaps/{}.xml", n + 1), part)?;
+    }
+    files.write("robots.txt", &render_robots(&config.crawlers))?;
+    files.write("ai.txt", &render_ai_txt(&config.crawlers))?;
+    files.write("llms.txt", &render_llms_txt(&blogposts, &config))?;
+
+    files.copy_assets(&root.join("assets"), Path::new("assets"))?;
+
+    Ok(files.written)
+}
+
+/// Makes `out` an empty directory. Only directories that are empty or hold a
+/// previous export are cleared, so a typo can't wipe out anything else.
+fn prepare(out: &Path) -> Result<()> {
+    if out.exists() {
+        let empty = std::fs::read_dir(out)?.next().is_none();
+        if !empty && !out.join(MARKER).exists() {
+            return Err(eyre!(format!(
+                "Error exporting ({}): directory is not empty and not a previous export",
+                out.display()
+            )));
+        }
+        std::fs::remove_dir_all(out)?;
+    }
+    std::fs::create_dir_all(out)?;
+    std::fs::write(out.join(MARKER), "")?;
+    Ok(())
+}
+
+/// Whether `name` stays a single path segment when used as a file name.
+fn is_safe(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    matches!(components.next(), Some(Component::Normal(_))) && components.next().is_none()
+}
+
+/// A post page without its view counter, which only makes sense live.
+fn without_views(html: &str) -> String {
+    let Some((head, tail)) = html.split_once(VIEWS_MARKER) else {
+        return html.to_string();
+    };
+    let head = head.strip_suffix(" | ").unwrap_or(head);
+    let tail = tail.strip_prefix(" view(s)").unwrap_or(tail);
+    format!("{head}{tail}")
+}
+
+/// Puts `base_path` in front of root-relative urls in HTML attributes and
+/// CSS `url()`s.
+fn rebase(text: &str, base_path: &str) -> String {
+    if base_path.is_empty() {
+        return text.to_string();
+    }
+    let mut text = text.to_string();
+    for prefix in ["href=\"", "src=\"", "action=\"", "url('", "url(\"", "url("] {
+        let from = format!("{prefix}/");
+        let mut rebased = String::with_capacity(text.len());
+        let mut rest = text.as_str();
+        while let Some(at) = rest.find(&from) {
+            let (before, after) = rest.split_at(at + prefix.len());
+            rebased.push_str(before);
+            // protocol-relative urls point at another host
+            if !after.starts_with("//") {
+                rebased.push_str(base_path);
+            }
+            rest = after;
+        }
+        rebased.push_str(rest);
+        text = rebased;
+    }
+    text
+}
+
+struct Files {
+    out: PathBuf,
+    base_path: String,
+    written: usize,
+}
+
+impl Files {
+    fn write(&mut self, path: &str, contents: &str) -> Result<()> {
+        self.write_bytes(Path::new(path), contents.as_bytes())
+    }
+
+    fn html(&mut self, path: &str, html: &str) -> Result<()> {
+        self.write(path, &rebase(html, &self.base_path))
+    }
+
+    fn write_bytes(&mut self, path: &Path, contents: &[u8]) -> Result<()> {
+        let path = self.out.join(path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, contents)
+            .map_err(|err| eyre!(format!("Error writing {}: {err}", path.display())))?;
+        self.written += 1;
+        Ok(())
+    }
+
+    /// Copies the assets under `dir` to `to` in the output, rebasing
+    /// stylesheets. The precompressed siblings are left behind, they are
+    /// regenerated on startup and would no longer match rebased files.
+    fn copy_assets(&mut self, dir: &Path, to: &Path) -> Result<()> {
+        let mut entries = std::fs::read_dir(dir)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort();
+
+        for path in entries {
+            let Some(name) = path.file_name() else {
+                continue;
+            };
+            let target = to.join(name);
+            if path.is_dir() {
+                self.copy_assets(&path, &target)?;
+                continue;
+            }
+
+            let ext = path.extension().and_then(|e| e.to_str());
+            if matches!(ext, Some("br" | "gz")) {
+                continue;
+            }
+            let bytes = std::fs::read(&path)?;
+            if ext == Some("css") {
+                let css = rebase(&String::from_utf8_lossy(&bytes), &self.base_path);
+                self.write_bytes(&target, css.as_bytes())?;
+            } else {
+                self.write_bytes(&target, &bytes)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -31,13 +31,69 @@ pub fn header(title: &str, description: &str) -> Markup {
     }
 }
 
-pub fn footer() -> Markup {
+/// The year live pages are rendered in. Exports use the year of the newest
+/// post instead, so they don't change with the date.
+pub fn current_year() -> i32 {
+    chrono::Utc::now().year()
+}
+
+pub fn footer(year: i32) -> Markup {
     html! {
         footer {
             div.signet-block {
                 img.signet src="/assets/bergsoe.webp" alt="signet";
                 hr;
-                "© " (chrono::Utc::now().year().to_string()) " " a href="https://github.com/vilhelmbergsoe" { "Vilhelm Bergsøe" }
+                "© " (year.to_string()) " " a href="https://github.com/vilhelmbergsoe" { "Vilhelm Bergsøe" }
+            }
+        }
+    }
+}
+
+/// Inline SVG sparkline, scaled to the largest value. Renders nothing
+/// visible for an all-zero series beyond the baseline.
+pub fn sparkline(values: &[u64]) -> Markup {
+    const WIDTH: f64 = 120.0;
+    const HEIGHT: f64 = 24.0;
+
+    let max = values.iter().copied().max().unwrap_or(0).max(1) as f64;
+    let step = WIDTH / (values.len().max(2) - 1) as f64;
+    let points: Vec<String> = values
+        .iter()
+        .enumerate()
+        .map(|(i, v)| {
+            format!(
+                "{:.1},{:.1}",
+                i as f64 * step,
+                HEIGHT - 1.0 - (*v as f64 / max) * (HEIGHT - 2.0)
+            )
+        })
+        .collect();
+
+    html! {
+        svg.sparkline viewBox=(format!("0 0 {WIDTH} {HEIGHT}")) width=(WIDTH) height=(HEIGHT) role="img" {
+            polyline points=(points.join(" ")) fill="none" stroke="currentColor" stroke-width="1.5" stroke-linejoin="round" {}
+        }
+    }
+}
+
+/// Inline SVG bar chart with one bar per label, hovering a bar shows its value.
+pub fn bar_chart(labels: &[String], values: &[u64]) -> Markup {
+    const WIDTH: f64 = 600.0;
+    const HEIGHT: f64 = 120.0;
+    const GAP: f64 = 2.0;
+
+    let max = values.iter().copied().max().unwrap_or(0).max(1) as f64;
+    let bar_width = WIDTH / values.len().max(1) as f64;
+
+    html! {
+        svg.chart viewBox=(format!("0 0 {WIDTH} {HEIGHT}")) preserveAspectRatio="none" role="img" {
+            @for (i, (label, value)) in labels.iter().zip(values).enumerate() {
+                @let height = (*value as f64 / max * (HEIGHT - 1.0)).max(1.0);
+                rect x=(format!("{:.1}", i as f64 * bar_width)) y=(format!("{:.1}", HEIGHT - height))
+                    width=(format!("{:.1}", (bar_width - GAP).max(1.0))) height=(format!("{:.1}", height))
+                    fill="currentColor" {
+                    title { (label) ": " (value) }
+                }
             }
         }
     }
@@ -0,0 +1,205 @@
+use axum::{
+    extract::{rejection::FormRejection, State},
+    http::{header, HeaderMap},
+    response::{IntoResponse, Redirect, Response},
+    Form,
+};
+use maud::html;
+use serde::Deserialize;
+
+use crate::blocklist::ActiveBans;
+use crate::error::AppError;
+use crate::fragments::{current_year, footer, header};
+use crate::proxy::ClientInfo;
+use crate::SharedState;
+
+/// Set by logging in, holds the digest of the token rather than the token.
+const SESSION_COOKIE: &str = "admin_session";
+
+#[derive(Deserialize)]
+pub struct LoginForm {
+    token: String,
+}
+
+#[derive(Deserialize)]
+pub struct UnbanForm {
+    ip: Option<String>,
+    agent: Option<String>,
+}
+
+/// Whether `digest` is that of the configured token. Comparing digests keeps
+/// the check from leaking the token through timing.
+fn is_token(state: &SharedState, digest: blake3::Hash) -> bool {
+    state
+        .config
+        .admin_token
+        .as_ref()
+        .is_some_and(|token| blake3::hash(token.as_bytes()) == digest)
+}
+
+/// Admin pages are only reachable with the configured token, and don't exist
+/// at all without one. The token comes as `Authorization: Bearer`, or from
+/// the cookie [`handle_login`] sets, never in the url where it would end up
+/// in logs and traces.
+pub fn authorized(state: &SharedState, headers: &HeaderMap) -> bool {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if bearer.is_some_and(|token| is_token(state, blake3::hash(token.trim().as_bytes()))) {
+        return true;
+    }
+
+    headers
+        .get_all(header::COOKIE)
+        .into_iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .filter(|(name, _)| *name == SESSION_COOKIE)
+        .any(|(_, value)| blake3::Hash::from_hex(value).is_ok_and(|d| is_token(state, d)))
+}
+
+/// `/admin/login`, asks for the token.
+pub async fn handle_login_form(State(state): State<SharedState>) -> Response {
+    if state.config.admin_token.is_none() {
+        return AppError::NotFound.into_response();
+    }
+
+    html! {
+        (header("Vilhelm Bergsøe - Admin", "Admin login"))
+        main {
+            section #h {
+                h2 { "Admin" }
+                form method="post" action="/admin/login" {
+                    input type="password" name="token" placeholder="Token" aria-label="Token" autocomplete="current-password";
+                    " "
+                    button { "Log in" }
+                }
+            }
+        }
+        (footer(current_year()))
+    }
+    .into_response()
+}
+
+/// Checks the posted token and keeps it in a cookie only the admin pages get
+/// to see, and only when navigating within the site.
+pub async fn handle_login(
+    State(state): State<SharedState>,
+    client: ClientInfo,
+    form: Result<Form<LoginForm>, FormRejection>,
+) -> Response {
+    let form = match form {
+        Ok(Form(form)) => form,
+        Err(rejection) => return AppError::BadRequest(rejection.body_text()).into_response(),
+    };
+    let digest = blake3::hash(form.token.as_bytes());
+    if !is_token(&state, digest) {
+        return AppError::NotFound.into_response();
+    }
+
+    let secure = if client.scheme == "https" {
+        "; Secure"
+    } else {
+        ""
+    };
+    let cookie =
+        format!("{SESSION_COOKIE}={digest}; Path=/admin; HttpOnly; SameSite=Strict{secure}");
+    (
+        [(header::SET_COOKIE, cookie)],
+        Redirect::to("/admin/blocklist"),
+    )
+        .into_response()
+}
+
+pub async fn handle_blocklist(State(state): State<SharedState>, headers: HeaderMap) -> Response {
+    if !authorized(&state, &headers) {
+        return AppError::NotFound.into_response();
+    }
+    let ActiveBans { ips, agents } = state.blocklist.list();
+
+    html! {
+        (header("Vilhelm Bergsøe - Blocklist", "Clients banned for ignoring robots.txt"))
+        main {
+            section #h .stats {
+                h2 { "Blocklist" }
+                p .stats-note {
+                    "Clients that fetched a path robots.txt disallows for them, banned for "
+                    (state.config.blocklist.ban_hours) " hours."
+                }
+
+                h3 { "Addresses" }
+                @if ips.is_empty() {
+                    p { "No banned addresses." }
+                } @else {
+                    table {
+                        tr { th { "IP" } th { "Reason" } th { "Until" } th {} }
+                        @for (ip, ban) in &ips {
+                            tr {
+                                td { (ip) }
+                                td { (ban.reason) }
+                                td { (ban.until.format("%Y-%m-%d %H:%M")) }
+                                td {
+                                    form method="post" action="/admin/blocklist/unban" {
+                                        input type="hidden" name="ip" value=(ip);
+                                        button { "Unban" }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+
+                h3 { "Bots" }
+                @if agents.is_empty() {
+                    p { "No banned bots." }
+                } @else {
+                    table {
+                        tr { th { "Bot" } th { "Reason" } th { "Until" } th {} }
+                        @for (agent, ban) in &agents {
+                            tr {
+                                td { (agent) }
+                                td { (ban.reason) }
+                                td { (ban.until.format("%Y-%m-%d %H:%M")) }
+                                td {
+                                    form method="post" action="/admin/blocklist/unban" {
+                                        input type="hidden" name="agent" value=(agent);
+                                        button { "Unban" }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        (footer(current_year()))
+    }
+    .into_response()
+}
+
+pub async fn handle_unban(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    form: Result<Form<UnbanForm>, FormRejection>,
+) -> Response {
+    if !authorized(&state, &headers) {
+        return AppError::NotFound.into_response();
+    }
+    let form = match form {
+        Ok(Form(form)) => form,
+        Err(rejection) => return AppError::BadRequest(rejection.body_text()).into_response(),
+    };
+
+    if let Some(ip) = form.ip.and_then(|ip| ip.parse().ok()) {
+        tracing::info!("unbanning {}", ip);
+        state.blocklist.unban_ip(ip);
+    }
+    if let Some(agent) = form.agent {
+        tracing::info!("unbanning bot {}", agent);
+        state.blocklist.unban_agent(&agent);
+    }
+
+    Redirect::to("/admin/blocklist").into_response()
+}
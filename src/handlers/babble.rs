@@ -0,0 +1,58 @@
+use std::convert::Infallible;
+use std::time::Duration;
+
+use axum::{
+    body::{Bytes, StreamBody},
+    extract::State,
+    http::{header, Uri},
+    response::{IntoResponse, Response},
+};
+use futures_util::{stream, StreamExt};
+
+use crate::babble::Babbler;
+use crate::error::AppError;
+use crate::SharedState;
+
+/// Tarpit for crawlers that ignore robots.txt. Every url under `/babble/`
+/// is an endless, deterministic page of Markov chain text that trickles out
+/// slowly and links to yet more babble.
+pub async fn handle_babble(State(state): State<SharedState>, uri: Uri) -> Response {
+    let config = &state.config.babble;
+
+    // past the cap the connection is dropped quickly instead of held open
+    let Ok(permit) = state.babble_slots.clone().try_acquire_owned() else {
+        return AppError::Unavailable {
+            retry_after: Some(Duration::from_secs(60)),
+        }
+        .into_response();
+    };
+    // the whole response comes from one babbler, even across a reload
+    let content = state.content();
+    if content.babbler.is_empty() {
+        return AppError::NotFound.into_response();
+    }
+
+    let mut rng = Babbler::rng(uri.path());
+    let head = content.babbler.head(&mut rng).into_string();
+    let delay = Duration::from_millis(config.chunk_delay_ms);
+    let paragraphs = config.paragraphs;
+
+    let body = stream::unfold(
+        (content, rng, 0, permit),
+        move |(content, mut rng, sent, permit)| async move {
+            if sent == paragraphs {
+                return None;
+            }
+            tokio::time::sleep(delay).await;
+            let chunk = content.babbler.paragraph(&mut rng).into_string();
+            Some((Ok(Bytes::from(chunk)), (content, rng, sent + 1, permit)))
+        },
+    );
+    let head = stream::once(async move { Ok::<_, Infallible>(Bytes::from(head)) });
+
+    (
+        [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
+        StreamBody::new(head.chain(body)),
+    )
+        .into_response()
+}
@@ -1,86 +1,94 @@
-use std::{hash::{DefaultHasher, Hasher, Hash}, net::SocketAddr};
-
 use axum::{
-    extract::{ConnectInfo, Path, State},
-    http::StatusCode,
+    extract::{Path, State},
+    http::{HeaderMap, Uri},
     response::IntoResponse,
 };
-use maud::{html, PreEscaped};
-
-use rayon::prelude::*;
+use maud::{html, Markup, PreEscaped};
 
 use crate::{
-    handle_404,
     fragments::{footer, header},
+    handlers::not_found,
+    proxy::ClientInfo,
+    views::track_hit,
+    BlogPost,
     SharedState,
-    UserId
 };
 
+/// Stand-in for the view count, spliced in per request by the page cache.
+pub const VIEWS_MARKER: &str = "<!--views-->";
+
 pub async fn handle_blog(
     Path(url): Path<String>,
     State(state): State<SharedState>,
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    client: ClientInfo,
+    uri: Uri,
+    headers: HeaderMap,
 ) -> impl IntoResponse {
-    let blogpost = state
-        .blogposts
-        .par_iter()
-        .find_first(|blogpost| blogpost.url == url);
+    let content = state.content();
+    if let Some(url) = url.strip_suffix(".md") {
+        let Some(markdown) = content.pages.markdown(url) else {
+            return not_found(&state, &uri, &headers);
+        };
+        track_hit(&state, &format!("/blog/{}.md", url), client.ip, &headers);
+        return markdown.respond(&headers);
+    }
 
-    if let Some(blogpost) = &blogpost {
-        let mut hasher = DefaultHasher::new();
-        state.salt.hash(&mut hasher);
-        addr.ip().hash(&mut hasher);
-        let user_id: UserId = hasher.finish();
+    let Some(page) = content.pages.post(&url) else {
+        return not_found(&state, &uri, &headers);
+    };
 
-        let mut write_guard = state.total_views.write().await;
-        write_guard
-            .entry(blogpost.title.clone())
-            .or_default()
-            .insert(user_id);
-    }
+    let total_views = match track_hit(&state, &format!("/blog/{}", url), client.ip, &headers) {
+        Some(visitor) => {
+            let mut write_guard = state.total_views.write().await;
+            let views = write_guard.entry(url).or_default();
+            if views.record(visitor) {
+                state.view_store.record(&page.post.url, visitor.id);
+            }
+            views.count()
+        }
+        None => state
+            .total_views
+            .read()
+            .await
+            .get(&url)
+            .map_or(0, |views| views.count()),
+    };
 
-    match blogpost {
-        Some(blogpost) => {
-            let read_guard = state.total_views.read().await;
-            let total_views = read_guard
-                .get(&blogpost.title)
-                .map_or(0, |views_set| views_set.len());
-            (
-                StatusCode::OK,
-                html! {
-                    (header(&format!("Vilhelm Bergsøe - {}", blogpost.title), "Vilhelm Bergsøe - Blog"))
-                    main {
-                        section #h {
-                            div .blogpost {
-                                h2 .blogtitle { (blogpost.title) }
-                                span style="opacity: 0.7;" {
-                                    (blogpost.date.format("%a %d %b %Y"))
-                                    // 200 words per minute estimate
-                                    (format!(" - {} min read | {} view(s)" , blogpost.estimated_read_time, total_views))
-                                }
-                                br;
-                                p {
-                                    (PreEscaped(&blogpost.content))
-                                }
-                            }
+    page.respond(total_views, &headers)
+}
 
-                            div {
-                                "tags: ["
-                                @for (i, tag) in blogpost.tags.iter().enumerate() {
-                                    @if i > 0 {
-                                        ", "
-                                    }
-                                    a href=(format!("/tag/{}", tag)) { (tag) }
-                                }
-                                "]"
-                            }
-                        }
+pub fn render_blog(blogpost: &BlogPost, year: i32) -> Markup {
+    html! {
+        (header(&format!("Vilhelm Bergsøe - {}", blogpost.title), "Vilhelm Bergsøe - Blog"))
+        main {
+            section #h {
+                div .blogpost {
+                    h2 .blogtitle { (blogpost.title) }
+                    span style="opacity: 0.7;" {
+                        (blogpost.date.format("%a %d %b %Y"))
+                        // 200 words per minute estimate
+                        (format!(" - {} min read | ", blogpost.estimated_read_time))
+                        (PreEscaped(VIEWS_MARKER)) " view(s)"
+                    }
+                    br;
+                    p {
+                        (PreEscaped(&blogpost.content))
                     }
+                }
 
-                    (footer())
-                },
-            )
+                div {
+                    "tags: ["
+                    @for (i, tag) in blogpost.tags.iter().enumerate() {
+                        @if i > 0 {
+                            ", "
+                        }
+                        a href=(format!("/tag/{}", tag)) { (tag) }
+                    }
+                    "]"
+                }
+            }
         }
-        None => handle_404().await,
+
+        (footer(year))
     }
 }
@@ -0,0 +1,84 @@
+use std::fmt::Write;
+
+use axum::{extract::State, http::HeaderMap, response::IntoResponse};
+
+use crate::config::{Config, CrawlerPolicy};
+use crate::{BlogPost, SharedState};
+
+pub async fn handle_robots(State(state): State<SharedState>, headers: HeaderMap) -> impl IntoResponse {
+    state.content().pages.robots().respond(&headers)
+}
+
+pub async fn handle_ai_txt(State(state): State<SharedState>, headers: HeaderMap) -> impl IntoResponse {
+    state.content().pages.ai_txt().respond(&headers)
+}
+
+pub async fn handle_llms_txt(State(state): State<SharedState>, headers: HeaderMap) -> impl IntoResponse {
+    state.content().pages.llms_txt().respond(&headers)
+}
+
+pub fn render_robots(policy: &CrawlerPolicy) -> String {
+    let mut out = String::new();
+
+    for group in &policy.groups {
+        for agent in &group.agents {
+            let _ = writeln!(out, "User-agent: {agent}");
+        }
+        for path in &group.disallow {
+            let _ = writeln!(out, "Disallow: {path}");
+        }
+        for path in &group.allow {
+            let _ = writeln!(out, "Allow: {path}");
+        }
+        out.push('\n');
+    }
+
+    if let Some(sitemap) = &policy.sitemap {
+        let _ = writeln!(out, "Sitemap: {sitemap}");
+    }
+
+    out
+}
+
+/// Spawning's ai.txt, which only says whether the content may be used to
+/// train models.
+pub fn render_ai_txt(policy: &CrawlerPolicy) -> String {
+    let rule = if policy.ai_training { "Allow" } else { "Disallow" };
+    format!("# https://site.spawning.ai/spawning-ai-txt\nUser-Agent: *\n{rule}: /\n")
+}
+
+/// llms.txt (https://llmstxt.org), pointing at the markdown source of each
+/// post. Archived posts go under "Optional", which readers may skip.
+pub fn render_llms_txt(blogposts: &[BlogPost], config: &Config) -> String {
+    let mut out = String::from(
+        "# Vilhelm Bergsøe\n\n> Vilhelm Bergsøe's personal website and blog\n\n\
+         Every post is available as plain markdown by appending `.md` to its url.\n",
+    );
+
+    let mut section = |title: &str, archived: bool| {
+        let mut posts = blogposts.iter().filter(|p| p.archived == archived).peekable();
+        if posts.peek().is_none() {
+            return;
+        }
+        let _ = write!(out, "\n## {title}\n\n");
+        for post in posts {
+            let _ = writeln!(
+                out,
+                "- [{}]({}/blog/{}.md): {}",
+                post.title,
+                config.base_url,
+                post.url,
+                post.date.format("%Y-%m-%d")
+            );
+        }
+    };
+    section("Posts", false);
+    section("Optional", true);
+
+    out
+}
+
+/// A post as markdown, with its title put back on top.
+pub fn render_markdown(post: &BlogPost) -> String {
+    format!("# {}\n\n{}", post.title, post.markdown)
+}
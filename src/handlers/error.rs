@@ -0,0 +1,25 @@
+use axum::http::StatusCode;
+use maud::{html, Markup};
+
+use crate::fragments::{current_year, footer, header};
+
+/// The page shown for an error status, with a line on what happened.
+pub fn render_error(status: StatusCode, message: &str) -> Markup {
+    let title = format!(
+        "{} {}",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or("Error")
+    );
+
+    html! {
+        (header(&format!("Vilhelm Bergsøe - {}", title), &title))
+        main {
+            section #h {
+                h2 { (title) }
+                p { (message) }
+                p { a href="/" { "Back to the front page" } }
+            }
+        }
+        (footer(current_year()))
+    }
+}
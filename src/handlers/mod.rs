@@ -1,16 +1,33 @@
 mod root;
+mod admin;
+mod babble;
 mod blog;
+mod crawlers;
+mod error;
 mod tag;
 
 mod sitemap;
 mod rss_feed;
 mod stats;
 mod not_found;
+mod ops;
+mod privacy_policy;
+mod search;
 
-pub use blog::handle_blog;
-pub use not_found::handle_404;
-pub use root::root;
-pub use rss_feed::handle_rss;
-pub use tag::handle_tag;
-pub use sitemap::handle_sitemap;
-pub use stats::handle_stats;
+pub use admin::{handle_blocklist, handle_login, handle_login_form, handle_unban};
+pub use babble::handle_babble;
+pub use blog::{handle_blog, render_blog, VIEWS_MARKER};
+pub use crawlers::{
+    handle_ai_txt, handle_llms_txt, handle_robots, render_ai_txt, render_llms_txt, render_markdown,
+    render_robots,
+};
+pub use error::render_error;
+pub use not_found::{handle_404, not_found, render_404};
+pub use ops::{handle_healthz, handle_metrics, handle_readyz};
+pub use privacy_policy::{handle_privacy, render_privacy};
+pub use search::handle_search;
+pub use root::{render_root, root};
+pub use rss_feed::{handle_rss, render_rss};
+pub use tag::{handle_tag, render_tag};
+pub use sitemap::{handle_sitemap, handle_sitemap_part, render_sitemap};
+pub use stats::{handle_stats, handle_stats_json};
@@ -1,15 +1,137 @@
-use axum::http::StatusCode;
-use maud::{html, PreEscaped};
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use maud::{html, Markup};
 
-use crate::fragments::{footer, header};
+use crate::fragments::{current_year, footer, header};
+use crate::{BlogPost, SharedState};
 
-pub async fn handle_404() -> (StatusCode, PreEscaped<String>) {
+/// Posts suggested on a 404 page at most.
+const SUGGESTIONS: usize = 3;
+
+pub async fn handle_404(
+    State(state): State<SharedState>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Response {
+    not_found(&state, &uri, &headers)
+}
+
+/// Answers a request for something that isn't there: a 301 when it moved,
+/// otherwise a 404 suggesting the posts closest to what was asked for.
+pub fn not_found(state: &SharedState, uri: &Uri, headers: &HeaderMap) -> Response {
+    let content = state.content();
+    if let Some(redirect) = content.redirects.redirect(uri) {
+        return redirect;
+    }
+
+    let slug = requested_slug(uri.path());
+    let suggestions = suggest(&slug, &content.blogposts);
+    if suggestions.is_empty() {
+        return content.pages.not_found().respond(headers);
+    }
     (
         StatusCode::NOT_FOUND,
-        html! {
-            (header("Vilhelm Bergsøe - 404 Not Found", "404 Not Found"))
-            p { "404 Not found" }
-            (footer())
-        },
+        render_404(Some(&slug), &suggestions, current_year()),
     )
+        .into_response()
+}
+
+/// The last path segment, which is where the slug is in any url shape the
+/// site has had.
+fn requested_slug(path: &str) -> String {
+    let segment = path.trim_end_matches('/').rsplit('/').next().unwrap_or("");
+    let segment = segment
+        .strip_suffix(".html")
+        .or_else(|| segment.strip_suffix(".md"))
+        .unwrap_or(segment);
+    segment.to_lowercase()
+}
+
+/// Levenshtein distance, over chars.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// The posts whose url is within half its length of `slug`, closest first.
+fn suggest<'a>(slug: &str, blogposts: &'a [BlogPost]) -> Vec<&'a BlogPost> {
+    if slug.is_empty() {
+        return Vec::new();
+    }
+
+    let mut scored: Vec<(usize, &BlogPost)> = blogposts
+        .iter()
+        .map(|post| (edit_distance(slug, &post.url), post))
+        .filter(|(distance, post)| *distance <= post.url.chars().count().max(slug.len()) / 2)
+        .collect();
+    scored.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| b.1.date.cmp(&a.1.date)));
+    scored
+        .into_iter()
+        .take(SUGGESTIONS)
+        .map(|(_, post)| post)
+        .collect()
+}
+
+/// `query` pre-fills the search form, which is left out without one.
+pub fn render_404(query: Option<&str>, suggestions: &[&BlogPost], year: i32) -> Markup {
+    html! {
+        (header("Vilhelm Bergsøe - 404 Not Found", "404 Not Found"))
+        main {
+            section #h {
+                h2 { "404 Not Found" }
+                p { "There is nothing here." }
+                @if !suggestions.is_empty() {
+                    p { "Perhaps you were looking for:" }
+                    ul {
+                        @for blogpost in suggestions {
+                            li {
+                                span.blog-date { (blogpost.date.format("D%d-%m-%Y")) }
+                                a href=(format!("/blog/{}", blogpost.url)) { (blogpost.title) }
+                            }
+                        }
+                    }
+                }
+                @if let Some(query) = query {
+                    form method="get" action="/search" {
+                        input type="search" name="q" value=(query.replace(['-', '_'], " ")) placeholder="Search posts" aria-label="Search posts";
+                        " "
+                        button { "Search" }
+                    }
+                }
+                p { a href="/" { "Back to the front page" } }
+            }
+        }
+        (footer(year))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit_distance_counts_single_char_edits() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("nix", ""), 3);
+        assert_eq!(edit_distance("", "nix"), 3);
+        assert_eq!(edit_distance("new-website", "new-website"), 0);
+        assert_eq!(edit_distance("new-webiste", "new-website"), 2);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("neural-network", "neural-networks"), 1);
+        // chars, not bytes
+        assert_eq!(edit_distance("bergsøe", "bergsoe"), 1);
+    }
 }
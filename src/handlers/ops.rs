@@ -0,0 +1,82 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use crate::error::AppError;
+use crate::proxy::ClientInfo;
+use crate::{metrics, SharedState};
+
+/// Operational endpoints are for the allowed networks, or anyone with the
+/// token as `Authorization: Bearer`. Everyone else gets the regular 404.
+/// Unix socket clients have no address to check, so they need the token.
+fn allowed(state: &SharedState, client: &ClientInfo, headers: &HeaderMap) -> bool {
+    let ops = &state.config.ops;
+    if !client.unix && ops.allow.iter().any(|net| net.contains(&client.ip)) {
+        return true;
+    }
+
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    match (&ops.token, bearer) {
+        // compare digests so the check doesn't leak the token through timing
+        (Some(expected), Some(token)) => {
+            blake3::hash(expected.as_bytes()) == blake3::hash(token.trim().as_bytes())
+        }
+        _ => false,
+    }
+}
+
+pub async fn handle_healthz(
+    State(state): State<SharedState>,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Response {
+    if !allowed(&state, &client, &headers) {
+        return AppError::NotFound.into_response();
+    }
+    "ok\n".into_response()
+}
+
+/// Ready once the content loaded without errors, otherwise lists them.
+pub async fn handle_readyz(
+    State(state): State<SharedState>,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Response {
+    if !allowed(&state, &client, &headers) {
+        return AppError::NotFound.into_response();
+    }
+    let content = state.content();
+    if content.content_errors.is_empty() {
+        return "ready\n".into_response();
+    }
+
+    let mut body = String::from("not ready, content failed to load:\n");
+    for error in &content.content_errors {
+        body.push_str(error);
+        body.push('\n');
+    }
+    (StatusCode::SERVICE_UNAVAILABLE, body).into_response()
+}
+
+pub async fn handle_metrics(
+    State(state): State<SharedState>,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Response {
+    if !allowed(&state, &client, &headers) {
+        return AppError::NotFound.into_response();
+    }
+    (
+        [(
+            header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        metrics::render(&state).await,
+    )
+        .into_response()
+}
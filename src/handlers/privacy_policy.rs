@@ -0,0 +1,80 @@
+use axum::{extract::State, http::HeaderMap, response::IntoResponse};
+use maud::{html, Markup};
+
+use crate::config::StatsConfig;
+use crate::fragments::{footer, header};
+use crate::SharedState;
+
+pub async fn handle_privacy(State(state): State<SharedState>, headers: HeaderMap) -> impl IntoResponse {
+    state.content().pages.privacy().respond(&headers)
+}
+
+/// The privacy policy, written against the settings the server runs with so
+/// it can't drift from what is actually collected.
+pub fn render_privacy(config: &StatsConfig, year: i32) -> Markup {
+    html! {
+        (header("Vilhelm Bergsøe - Privacy", "What this site records about visitors"))
+        main {
+            section #h {
+                h2 { "Privacy" }
+                p {
+                    "This site sets no cookies, runs no JavaScript and embeds nothing from third parties. "
+                    "It keeps a small amount of anonymous statistics, described below."
+                }
+
+                h3 { "What is counted" }
+                ul {
+                    li {
+                        "When you open a blog post, a visitor id is computed from your IP address and user agent "
+                        "using a keyed BLAKE3 hash. Neither the IP address nor the user agent is stored."
+                    }
+                    li {
+                        "The key for that hash is random and replaced every day at midnight UTC. "
+                        "It's saved on the server until then, so a restart doesn't count anyone twice. "
+                        "The old key is discarded, so ids from different days can't be linked to each other or to you."
+                    }
+                    li {
+                        "The id is used to count each visitor once per post per day. "
+                        "The post, the id and the time of the view are stored for "
+                        (config.retention_days) " days, after which only the daily total per post is kept."
+                    }
+                    li {
+                        "For every page, the domain of the referring site (never the full address) and whether "
+                        "you arrived from outside the site are added to daily tallies without any visitor id, "
+                        "kept for " (config.retention_days) " days."
+                    }
+                    li {
+                        "Crawlers, feed readers and scripts are recognised by their user agent and only tallied "
+                        "per bot name and page, they don't count as views."
+                    }
+                }
+
+                h3 { "Opting out" }
+                p {
+                    "Requests sent with " code { "DNT: 1" } " (Do Not Track) or " code { "Sec-GPC: 1" }
+                    " (Global Privacy Control) are not counted at all."
+                }
+
+                h3 { "Where it goes" }
+                p {
+                    "The statistics stay on this server and are shown publicly on the " a href="/stats" { "stats page" }
+                    ". Nothing is shared with or sold to anyone."
+                }
+
+                h3 { "Server logs" }
+                p {
+                    "The server logs each request's method, path, response status and client IP address "
+                    "for debugging and abuse prevention. These logs are not part of the statistics."
+                }
+
+                h3 { "Blocklist" }
+                p {
+                    "Clients that fetch pages robots.txt asks them to stay away from have their IP address "
+                    "put on a blocklist, together with the path that got them banned. Entries expire after "
+                    "a while and are deleted."
+                }
+            }
+        }
+        (footer(year))
+    }
+}
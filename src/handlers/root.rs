@@ -1,11 +1,25 @@
-use axum::{extract::State, response::IntoResponse};
-use chrono::Datelike;
-use maud::html;
+use axum::{
+    extract::State,
+    http::HeaderMap,
+    response::IntoResponse,
+};
+use maud::{html, Markup};
 
 use crate::fragments::{footer, header};
-use crate::SharedState;
+use crate::proxy::ClientInfo;
+use crate::views::track_hit;
+use crate::{BlogPost, SharedState};
 
-pub async fn root(State(state): State<SharedState>) -> impl IntoResponse {
+pub async fn root(
+    State(state): State<SharedState>,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    track_hit(&state, "/", client.ip, &headers);
+    state.content().pages.root().respond(&headers)
+}
+
+pub fn render_root(blogposts: &[BlogPost], year: i32) -> Markup {
     html! {
         (header("Vilhelm Bergsøe - Home", "Vilhelm Bergsøe's personal website and blog"))
         div style="position: absolute; left: -9999px; top: -9999px; width: 1px; height: 1px; overflow: hidden;" {
@@ -18,7 +32,7 @@ pub async fn root(State(state): State<SharedState>) -> impl IntoResponse {
             section #b {
                 h2 { "Blog " a href="/rss.xml" title="RSS Feed" { img .rss-icon src="/assets/rss.png" alt="rss"; } }
                 ul {
-                    @for blogpost in &state.blogposts {
+                    @for blogpost in blogposts {
                         @if !blogpost.archived {
                             li {
                                 span.blog-date { (blogpost.date.format("D%d-%m-%Y")) }
@@ -66,7 +80,7 @@ pub async fn root(State(state): State<SharedState>) -> impl IntoResponse {
 			"Go, Rust, C, Zig, JavaScript and more"
 		    }
 		    li { b { "Tools & Technologies: " } br;
-			  "Docker, Git, Linux (" ((chrono::Utc::now().year() - 2015).to_string()) "+ years 🐧), HTML, CSS, SQL, React, Nix"
+			  "Docker, Git, Linux (" ((year - 2015).to_string()) "+ years 🐧), HTML, CSS, SQL, React, Nix"
 		    }
 		    li { b { "Currently learning: " } br;
 			 "ML & Data science, Biochemistry"
@@ -86,6 +100,6 @@ pub async fn root(State(state): State<SharedState>) -> impl IntoResponse {
                 }
             }
         }
-        (footer())
+        (footer(year))
     }
 }
@@ -1,20 +1,15 @@
-use axum::{
-    body,
-    extract::State,
-    http::{Response, StatusCode},
-    response::IntoResponse,
-};
+use axum::{extract::State, http::HeaderMap, response::IntoResponse};
 
-use crate::{templates, SharedState};
+use crate::{templates, BlogPost, SharedState};
 
-pub async fn handle_rss(State(state): State<SharedState>) -> impl IntoResponse {
+pub async fn handle_rss(State(state): State<SharedState>, headers: HeaderMap) -> impl IntoResponse {
+    state.content().pages.rss().respond(&headers)
+}
+
+pub fn render_rss(blogposts: &[BlogPost], base_url: &str) -> String {
     let mut buf = Vec::new();
 
-    templates::rss_feed_xml(&mut buf, state.blogposts.clone()).unwrap();
+    templates::rss_feed_xml(&mut buf, blogposts, base_url.trim_end_matches('/')).expect("writing to a Vec cannot fail");
 
-    Response::builder()
-        .status(StatusCode::OK)
-        .header("content-type", "application/rss+xml")
-        .body(body::boxed(body::Full::from(buf)))
-        .unwrap()
+    String::from_utf8(buf).expect("rss template renders valid utf-8")
 }
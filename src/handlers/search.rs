@@ -0,0 +1,96 @@
+use axum::{
+    extract::{Query, State},
+    response::{IntoResponse, Response},
+};
+use maud::{html, Markup};
+use serde::Deserialize;
+
+use crate::fragments::{current_year, footer, header};
+use crate::{BlogPost, SharedState};
+
+/// Longer queries are cut off, searching is linear in their length.
+const MAX_QUERY_LEN: usize = 200;
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    #[serde(default)]
+    q: String,
+}
+
+pub async fn handle_search(
+    State(state): State<SharedState>,
+    query: Option<Query<SearchQuery>>,
+) -> Response {
+    let query: String = query
+        .map(|Query(query)| query.q)
+        .unwrap_or_default()
+        .chars()
+        .take(MAX_QUERY_LEN)
+        .collect();
+    let content = state.content();
+    let results = search(&query, &content.blogposts);
+    render_search(&query, &results).into_response()
+}
+
+/// Posts containing every word of `query`, ranked by where the words are:
+/// the title counts most, then tags, then the text.
+fn search<'a>(query: &str, blogposts: &'a [BlogPost]) -> Vec<&'a BlogPost> {
+    let terms: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+    if terms.is_empty() {
+        return Vec::new();
+    }
+
+    let mut scored: Vec<(usize, &BlogPost)> = blogposts
+        .iter()
+        .filter_map(|post| {
+            let title = post.title.to_lowercase();
+            let tags = post.tags.join(" ").to_lowercase();
+            let text = post.markdown.to_lowercase();
+
+            let mut score = 0;
+            for term in &terms {
+                let hits = 10 * title.matches(term.as_str()).count()
+                    + 5 * tags.matches(term.as_str()).count()
+                    + text.matches(term.as_str()).count();
+                if hits == 0 {
+                    return None;
+                }
+                score += hits;
+            }
+            Some((score, post))
+        })
+        .collect();
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| b.1.date.cmp(&a.1.date)));
+    scored.into_iter().map(|(_, post)| post).collect()
+}
+
+pub fn render_search(query: &str, results: &[&BlogPost]) -> Markup {
+    html! {
+        (header("Vilhelm Bergsøe - Search", "Search the blog posts"))
+        main {
+            section #b {
+                h2 { "Search" }
+                form method="get" action="/search" {
+                    input type="search" name="q" value=(query) placeholder="Search posts" aria-label="Search posts";
+                    " "
+                    button { "Search" }
+                }
+                @if !query.trim().is_empty() {
+                    @if results.is_empty() {
+                        p { "No posts match \"" (query) "\"." }
+                    } @else {
+                        ul {
+                            @for blogpost in results {
+                                li {
+                                    span.blog-date { (blogpost.date.format("D%d-%m-%Y")) }
+                                    a href=(format!("/blog/{}", blogpost.url)) { (blogpost.title) }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        (footer(current_year()))
+    }
+}
@@ -1,40 +1,287 @@
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+
 use axum::{
-    extract::State,
-    http::{header, HeaderMap, StatusCode},
-    response::IntoResponse,
+    extract::{Path, State},
+    http::{HeaderMap, Uri},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use quick_xml::{
+    events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event},
+    Writer,
 };
-use crate::SharedState;
-
-pub async fn handle_sitemap(State(state): State<SharedState>) -> impl IntoResponse {
-    let mut sitemap = String::from(r#"<?xml version="1.0" encoding="UTF-8"?>
-<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#);
-
-    // Add static pages
-    sitemap.push_str(r#"
-    <url>
-        <loc>https://bergsoe.net/</loc>
-    </url>"#);
-
-    // Add blog posts
-    for post in &state.blogposts {
-        if !post.archived {
-            let url = format!(
-                r#"
-    <url>
-        <loc>https://bergsoe.net/blog/{}</loc>
-        <lastmod>{}</lastmod>
-    </url>"#,
-                post.url,
-                post.date.format("%Y-%m-%d")
-            );
-            sitemap.push_str(&url);
+
+use crate::config::Config;
+use crate::handlers::not_found;
+use crate::{BlogPost, SharedState};
+
+/// Most urls a single sitemap may list, per the protocol.
+const MAX_URLS: usize = 50_000;
+/// Largest a single sitemap may be uncompressed, per the protocol.
+const MAX_BYTES: usize = 50 * 1024 * 1024;
+/// Most images a single url may list.
+const MAX_IMAGES: usize = 1_000;
+
+const SITEMAP_NS: &str = "http://www.sitemaps.org/schemas/sitemap/0.9";
+const IMAGE_NS: &str = "http://www.google.com/schemas/sitemap-image/1.1";
+
+pub async fn handle_sitemap(State(state): State<SharedState>, headers: HeaderMap) -> impl IntoResponse {
+    state.content().pages.sitemap().respond(&headers)
+}
+
+/// `/sitemaps/<n>.xml`, the parts `/sitemap.xml` indexes once the site no
+/// longer fits a single sitemap.
+pub async fn handle_sitemap_part(
+    Path(file): Path<String>,
+    State(state): State<SharedState>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Response {
+    let content = state.content();
+    let part = file
+        .strip_suffix(".xml")
+        .and_then(|n| n.parse::<usize>().ok())
+        .and_then(|n| content.pages.sitemap_part(n));
+    match part {
+        Some(page) => page.respond(&headers),
+        None => not_found(&state, &uri, &headers),
+    }
+}
+
+/// The sitemap, split up when it outgrows the protocol limits.
+pub struct Sitemaps {
+    /// Served at `/sitemap.xml`: every url, or an index of `parts`.
+    pub root: String,
+    /// Served at `/sitemaps/<n>.xml`, counting from 1. Empty unless split.
+    pub parts: Vec<String>,
+}
+
+struct Url {
+    loc: String,
+    lastmod: Option<DateTime<Utc>>,
+    images: Vec<String>,
+}
+
+/// A run of serialized `<url>` entries that fits in one sitemap.
+struct Chunk {
+    entries: Vec<u8>,
+    count: usize,
+    lastmod: Option<DateTime<Utc>>,
+}
+
+/// The sitemap of the site. `live` lists the pages only a running server
+/// has, like `/stats`, which a static export leaves out.
+pub fn render_sitemap(blogposts: &[BlogPost], config: &Config, live: bool) -> Sitemaps {
+    let base = config.base_url.trim_end_matches('/');
+    // writing into a Vec can't fail
+    write_sitemaps(&urls(blogposts, base, live), base).expect("writing sitemap to memory")
+}
+
+fn write_sitemaps(urls: &[Url], base: &str) -> io::Result<Sitemaps> {
+    let overhead = urlset(&[])?.len();
+
+    let mut chunks: Vec<Chunk> = Vec::new();
+    for url in urls {
+        let entry = write_url(url)?;
+        match chunks.last_mut() {
+            Some(chunk)
+                if chunk.count < MAX_URLS
+                    && overhead + chunk.entries.len() + entry.len() <= MAX_BYTES =>
+            {
+                chunk.entries.extend_from_slice(&entry);
+                chunk.count += 1;
+                chunk.lastmod = chunk.lastmod.max(url.lastmod);
+            }
+            _ => chunks.push(Chunk {
+                entries: entry,
+                count: 1,
+                lastmod: url.lastmod,
+            }),
+        }
+    }
+
+    if chunks.len() <= 1 {
+        let entries = chunks.first().map_or(&[][..], |chunk| &chunk.entries);
+        return Ok(Sitemaps {
+            root: urlset(entries)?,
+            parts: Vec::new(),
+        });
+    }
+
+    Ok(Sitemaps {
+        root: index(&chunks, base)?,
+        parts: chunks
+            .iter()
+            .map(|chunk| urlset(&chunk.entries))
+            .collect::<io::Result<_>>()?,
+    })
+}
+
+/// Every page worth crawling: the front page, standalone pages, tags and all
+/// posts, archived ones included.
+fn urls(blogposts: &[BlogPost], base: &str, live: bool) -> Vec<Url> {
+    let listed = || blogposts.iter().filter(|p| !p.archived);
+    let page = |path: &str, lastmod| Url {
+        loc: format!("{base}{path}"),
+        lastmod,
+        images: Vec::new(),
+    };
+
+    let mut urls = vec![
+        page("/", listed().map(BlogPost::last_modified).max()),
+        // these change with the code rather than the content
+        page("/privacy", None),
+    ];
+    if live {
+        urls.push(page("/stats", None));
+    }
+
+    for post in blogposts {
+        urls.push(Url {
+            loc: format!("{base}/blog/{}", encode(&post.url)),
+            lastmod: Some(post.last_modified()),
+            images: images(&post.content, base),
+        });
+    }
+
+    // tag pages only list posts that aren't archived
+    let tags: BTreeSet<&String> = listed().flat_map(|p| &p.tags).collect();
+    for tag in tags {
+        let lastmod = listed()
+            .filter(|p| p.tags.contains(tag))
+            .map(BlogPost::last_modified)
+            .max();
+        urls.push(page(&format!("/tag/{}", encode(tag)), lastmod));
+    }
+
+    urls
+}
+
+/// Percent-encodes a path segment.
+fn encode(segment: &str) -> String {
+    let mut out = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    out
+}
+
+/// Undoes the entity escaping comrak applies to attribute values.
+fn unescape(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#x27;", "'")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// The pictures a post embeds, as absolute urls, in order of appearance.
+fn images(html: &str, base: &str) -> Vec<String> {
+    let scheme = base.split_once("://").map_or("https", |(scheme, _)| scheme);
+
+    let mut images: Vec<String> = Vec::new();
+    for tag in html.split("<img ").skip(1) {
+        let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+        let Some(start) = tag.find("src=\"") else {
+            continue;
+        };
+        let src = &tag[start + 5..];
+        let src = unescape(&src[..src.find('"').unwrap_or(src.len())]);
+
+        let url = if src.contains("://") {
+            src
+        } else if src.starts_with("//") {
+            format!("{scheme}:{src}")
+        } else if src.starts_with('/') {
+            format!("{base}{src}")
+        } else if src.is_empty() || src.starts_with("data:") {
+            continue;
+        } else {
+            // relative to the post, which lives under /blog/
+            format!("{base}/blog/{src}")
+        };
+
+        if !images.contains(&url) {
+            images.push(url);
+        }
+        if images.len() == MAX_IMAGES {
+            break;
         }
     }
+    images
+}
+
+fn text<W: Write>(writer: &mut Writer<W>, name: &str, value: &str) -> io::Result<()> {
+    writer
+        .create_element(name)
+        .write_text_content(BytesText::new(value))?;
+    Ok(())
+}
 
-    sitemap.push_str("</urlset>");
+fn w3c_datetime(at: DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
 
-    let mut headers = HeaderMap::new();
-    headers.insert(header::CONTENT_TYPE, "application/xml".parse().unwrap());
+fn write_url(url: &Url) -> io::Result<Vec<u8>> {
+    let mut writer = Writer::new(Vec::new());
+    writer.create_element("url").write_inner_content(|w| {
+        text(w, "loc", &url.loc)?;
+        if let Some(lastmod) = url.lastmod {
+            text(w, "lastmod", &w3c_datetime(lastmod))?;
+        }
+        for image in &url.images {
+            w.create_element("image:image")
+                .write_inner_content(|w| text(w, "image:loc", image))?;
+        }
+        Ok(())
+    })?;
+    Ok(writer.into_inner())
+}
 
-    (StatusCode::OK, headers, sitemap)
+fn document(
+    root: BytesStart,
+    body: impl FnOnce(&mut Writer<Vec<u8>>) -> io::Result<()>,
+) -> io::Result<String> {
+    let name = String::from_utf8_lossy(root.name().as_ref()).into_owned();
+
+    let mut writer = Writer::new(Vec::new());
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+    writer.get_mut().write_all(b"\n")?;
+    writer.write_event(Event::Start(root))?;
+    body(&mut writer)?;
+    writer.write_event(Event::End(BytesEnd::new(name)))?;
+
+    Ok(String::from_utf8_lossy(&writer.into_inner()).into_owned())
+}
+
+/// A `<urlset>` around already serialized `<url>` entries.
+fn urlset(entries: &[u8]) -> io::Result<String> {
+    let root = BytesStart::new("urlset")
+        .with_attributes([("xmlns", SITEMAP_NS), ("xmlns:image", IMAGE_NS)]);
+    document(root, |w| w.get_mut().write_all(entries))
+}
+
+/// A `<sitemapindex>` pointing at each chunk's part.
+fn index(chunks: &[Chunk], base: &str) -> io::Result<String> {
+    let root = BytesStart::new("sitemapindex").with_attributes([("xmlns", SITEMAP_NS)]);
+    document(root, |w| {
+        for (n, chunk) in chunks.iter().enumerate() {
+            w.create_element("sitemap").write_inner_content(|w| {
+                text(w, "loc", &format!("{base}/sitemaps/{}.xml", n + 1))?;
+                if let Some(lastmod) = chunk.lastmod {
+                    text(w, "lastmod", &w3c_datetime(lastmod))?;
+                }
+                Ok(())
+            })?;
+        }
+        Ok(())
+    })
 }
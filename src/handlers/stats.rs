@@ -1,43 +1,194 @@
-use axum::extract::State;
-use axum::response::IntoResponse;
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
 use chrono::{Duration, Utc};
 use maud::html;
+use serde::Serialize;
 use std::fmt::Write;
 
+use crate::error::AppError;
+use crate::fragments::{bar_chart, current_year, footer, header, sparkline};
+use crate::ratelimit::LimiterMetrics;
+use crate::views::Report;
 use crate::SharedState;
 
-pub async fn handle_stats(State(state): State<SharedState>) -> impl IntoResponse {
-    let read_guard = state.total_views.read().await;
-    let total_views: usize = read_guard.values().map(|views_set| views_set.len()).sum();
-    let server_uptime = Utc::now() - state.uptime;
+const REPORT_DAYS: u32 = 30;
+const REPORT_WEEKS: u32 = 12;
+const REPORT_TOP: usize = 10;
+
+#[derive(Serialize)]
+struct Stats {
+    uptime_secs: i64,
+    total_views: u64,
+    rate_limit: LimiterMetrics,
+    #[serde(flatten)]
+    report: Report,
+}
 
-    let mut sorted_stats: Vec<_> = read_guard
-        .iter()
-        .map(|(title, views_set)| (title, views_set.len()))
-        .collect();
-    sorted_stats.sort_by(|a, b| b.1.cmp(&a.1));
+async fn load_stats(state: SharedState) -> Result<Stats, AppError> {
+    let uptime_secs = (Utc::now() - state.uptime).num_seconds();
+    let rate_limit = state.limiter.metrics();
+
+    let report = tokio::task::spawn_blocking(move || {
+        let mut report = state.view_store.report(REPORT_DAYS, REPORT_WEEKS, REPORT_TOP)?;
+        // views are keyed by url, posts that have since been removed show their url
+        let content = state.content();
+        for post in &mut report.posts {
+            if let Some(blogpost) = content.post(&post.url) {
+                post.title = blogpost.title.clone();
+            }
+        }
+        Ok::<_, rusqlite::Error>(report)
+    })
+    .await;
+
+    match report {
+        Ok(Ok(report)) => Ok(Stats {
+            uptime_secs,
+            total_views: report.posts.iter().map(|p| p.total).sum(),
+            rate_limit,
+            report,
+        }),
+        Ok(Err(err)) => Err(AppError::Internal(format!("failed reading stats: {err}"))),
+        Err(err) => Err(AppError::Internal(format!("stats task failed: {err}"))),
+    }
+}
+
+pub async fn handle_stats_json(State(state): State<SharedState>) -> Result<Response, AppError> {
+    Ok(Json(load_stats(state).await?).into_response())
+}
+
+pub async fn handle_stats(State(state): State<SharedState>) -> Response {
+    let banned = state.blocklist.list().count();
+    let stats = match load_stats(state).await {
+        Ok(stats) => stats,
+        Err(err) => return err.into_response(),
+    };
+    let report = &stats.report;
+    let recent_views: u64 = report.daily_views.iter().sum();
+    let recent_bot_hits: u64 = report.daily_bot_hits.iter().sum();
+    let max_referrer = report.referrers.first().map_or(1, |r| r.hits.max(1));
+    let max_entry = report.entry_pages.first().map_or(1, |e| e.hits.max(1));
+    let max_bot = report.bots.first().map_or(1, |b| b.hits.max(1));
 
     html! {
-        h1 { "Statistics" }
-        p style="opacity: 0.7; font-size: 0.9em; margin-top: -1.5em;" {
-            "Ephemeral stats, reset on server restart."
-        }
-        p {
-            strong { "Total Unique Views: " }
-            (total_views)
-        }
-        ul {
-            @for (title, views_count) in sorted_stats {
-                li {
-                    (title) ": " (views_count) " view(s)"
+        (header("Vilhelm Bergsøe - Statistics", "Vilhelm Bergsøe - Site statistics"))
+        main {
+            section #h .stats {
+                h2 { "Statistics" }
+                p .stats-note {
+                    "Unique visitors per post per day, crawlers and scripts are counted separately. See " a href="/privacy" { "privacy" } " for how they're counted. "
+                    a href="/stats.json" { "JSON" }
+                }
+
+                dl .stats-summary {
+                    div { dt { "Total unique views" } dd { (stats.total_views) } }
+                    div { dt { (format!("Human views, {} days", REPORT_DAYS)) } dd { (recent_views) } }
+                    div { dt { (format!("Bot hits, {} days", REPORT_DAYS)) } dd { (recent_bot_hits) } }
+                    div { dt { "Banned clients" } dd { (banned) } }
+                    div { dt { "Server uptime" } dd { (format_duration(Duration::seconds(stats.uptime_secs))) } }
+                }
+
+                h3 { "Daily views" }
+                (bar_chart(&report.days, &report.daily_views))
+                p .stats-axis { span { (report.days.first().map_or("", |d| d.as_str())) } span { "today" } }
+
+                h3 { "Daily bot hits" }
+                (bar_chart(&report.days, &report.daily_bot_hits))
+                p .stats-axis { span { (report.days.first().map_or("", |d| d.as_str())) } span { "today" } }
+
+                h3 { "Weekly views" }
+                (bar_chart(&report.weeks, &report.weekly_views))
+                p .stats-axis { span { (report.weeks.first().map_or("", |w| w.as_str())) } span { "this week" } }
+
+                h3 { "Posts" }
+                table {
+                    tr { th { "Post" } th { "Views" } th { (format!("Bot hits, {} days", REPORT_DAYS)) } th { "7 days" } th { (format!("{} days", REPORT_DAYS)) } }
+                    @for post in &report.posts {
+                        tr {
+                            td { a href=(format!("/blog/{}", post.url)) { (post.title) } }
+                            td { (post.total) }
+                            td { (post.bot_hits) }
+                            td { (post.daily.iter().rev().take(7).sum::<u64>()) }
+                            td { (sparkline(&post.daily)) }
+                        }
+                    }
+                }
+
+                h3 { "Top referrers" }
+                @if report.referrers.is_empty() {
+                    p { "No referrers yet." }
+                } @else {
+                    table {
+                        @for referrer in &report.referrers {
+                            tr {
+                                td { (referrer.name) }
+                                td .stats-bar { meter value=(referrer.hits) max=(max_referrer) {} }
+                                td { (referrer.hits) }
+                            }
+                        }
+                    }
+                }
+
+                h3 { "Top bots" }
+                @if report.bots.is_empty() {
+                    p { "No bots yet." }
+                } @else {
+                    table {
+                        @for bot in &report.bots {
+                            tr {
+                                td { (bot.name) }
+                                td .stats-bar { meter value=(bot.hits) max=(max_bot) {} }
+                                td { (bot.hits) }
+                            }
+                        }
+                    }
+                }
+
+                h3 { "Entry pages" }
+                @if report.entry_pages.is_empty() {
+                    p { "No entries yet." }
+                } @else {
+                    table {
+                        @for entry in &report.entry_pages {
+                            tr {
+                                td { a href=(entry.name) { (entry.name) } }
+                                td .stats-bar { meter value=(entry.hits) max=(max_entry) {} }
+                                td { (entry.hits) }
+                            }
+                        }
+                    }
+                }
+
+                h3 { "Rate limiting" }
+                p .stats-note {
+                    "Requests since the server started, limited per client address. "
+                    (stats.rate_limit.tracked_clients) " client(s) currently tracked."
+                }
+                table {
+                    tr { th { "Routes" } th { "Limit" } th { "Allowed" } th { "Throttled" } }
+                    @for class in &stats.rate_limit.classes {
+                        tr {
+                            td { (class.class) }
+                            td {
+                                @if class.per_minute == 0 {
+                                    "none"
+                                } @else {
+                                    (format!("{}/min, burst {}", class.per_minute, class.burst))
+                                }
+                            }
+                            td { (class.allowed) }
+                            td { (class.limited) }
+                        }
+                    }
                 }
             }
         }
-        p {
-            strong { "Server Uptime: " }
-            (format_duration(server_uptime))
-        }
+        (footer(current_year()))
     }
+    .into_response()
 }
 
 fn format_duration(duration: Duration) -> String {
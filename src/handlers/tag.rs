@@ -1,23 +1,35 @@
 use axum::{
     extract::{Path, State},
-    response::IntoResponse,
+    http::HeaderMap,
+    response::{IntoResponse, Response},
 };
-use maud::html;
-use rayon::prelude::*;
+use maud::{html, Markup};
 
-use crate::fragments::{footer, header};
-use crate::SharedState;
+use crate::fragments::{current_year, footer, header};
+use crate::proxy::ClientInfo;
+use crate::views::track_hit;
+use crate::{BlogPost, SharedState};
 
 pub async fn handle_tag(
     Path(tag): Path<String>,
     State(state): State<SharedState>,
-) -> impl IntoResponse {
-    let tagged_posts: Vec<_> = state
-        .blogposts
-        .par_iter()
-        .filter(|p| !p.archived && p.tags.contains(&tag))
-        .cloned()
-        .collect();
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Response {
+    match state.content().pages.tag(&tag) {
+        Some(page) => {
+            track_hit(&state, &format!("/tag/{}", tag), client.ip, &headers);
+            page.respond(&headers)
+        }
+        // unknown tags get an empty listing, same as before caching
+        None => render_tag(&tag, &state.content().blogposts, current_year()).into_response(),
+    }
+}
+
+pub fn render_tag(tag: &str, blogposts: &[BlogPost], year: i32) -> Markup {
+    let tagged_posts = blogposts
+        .iter()
+        .filter(|p| !p.archived && p.tags.iter().any(|t| t == tag));
 
     html! {
         (header(&format!("Vilhelm Bergsøe - Posts tagged with \"{}\"", tag), &format!("Vilhelm Bergsøe - Posts tagged with {}", tag)))
@@ -25,7 +37,7 @@ pub async fn handle_tag(
             section #b {
                 h2 { "Posts tagged with: " (tag) }
                 ul {
-                    @for blogpost in &tagged_posts {
+                    @for blogpost in tagged_posts {
                         li {
                             span.blog-date { (blogpost.date.format("D%d-%m-%Y")) }
                             a href=(format!("/blog/{}", blogpost.url)) { (blogpost.title) }
@@ -34,6 +46,6 @@ pub async fn handle_tag(
                 }
             }
         }
-        (footer())
+        (footer(year))
     }
 }
@@ -0,0 +1,206 @@
+//! Vilhelm Bergsøe's personal website and blog.
+//!
+//! [`Site`] builds the whole site as an axum [`Router`](axum::Router), to be
+//! served on its own by the `site` binary or mounted inside another
+//! application:
+//!
+//! ```no_run
+//! # async fn run() -> color_eyre::Result<()> {
+//! let (router, handle) = site::Site::new("./").build().await?;
+//! handle.start().await?;
+//! for post in handle.content().posts() {
+//!     println!("{} {}", post.url(), post.title());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::Result;
+use tokio::{
+    sync::{RwLock, Semaphore},
+    time::Instant,
+};
+
+use babble::Babbler;
+use blocklist::Blocklist;
+use bots::BotClassifier;
+use cache::PageCache;
+use config::Config;
+use dev::LiveReload;
+use metrics::Metrics;
+use privacy::VisitorIds;
+use ratelimit::RateLimiter;
+use redirects::Redirects;
+use robots::RobotsTxt;
+use views::{PostViews, ViewStore};
+
+pub use post::BlogPost;
+pub use site::{Site, SiteHandle};
+
+pub mod access_log;
+pub mod assets;
+pub mod babble;
+pub mod blocklist;
+pub mod bots;
+pub mod cache;
+pub mod config;
+pub mod dev;
+pub mod error;
+pub mod export;
+pub mod fragments;
+pub mod handlers;
+pub mod listener;
+pub mod metrics;
+pub mod post;
+pub mod privacy;
+pub mod proxy;
+pub mod ratelimit;
+pub mod redirects;
+pub mod render_cache;
+pub mod robots;
+pub mod security;
+pub mod site;
+pub mod telemetry;
+pub mod tls;
+pub mod views;
+
+pub type UserId = u64;
+
+pub struct State {
+    /// Swapped out as a whole on reload, see [`State::content`].
+    content: std::sync::RwLock<Arc<Content>>,
+    uptime: DateTime<Utc>,
+    total_views: RwLock<HashMap<String, PostViews>>,
+    view_store: ViewStore,
+    visitors: VisitorIds,
+    bots: BotClassifier,
+    robots: RobotsTxt,
+    blocklist: Blocklist,
+    limiter: RateLimiter,
+    metrics: Arc<Metrics>,
+    babble_slots: Arc<Semaphore>,
+    /// Set when serving with `--dev`.
+    dev: Option<Arc<LiveReload>>,
+    config: Config,
+}
+
+pub type SharedState = Arc<State>;
+
+impl State {
+    /// The content currently served. Requests hold on to the snapshot they
+    /// started with, so a reload never mixes old and new posts.
+    pub fn content(&self) -> Arc<Content> {
+        self.content
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn set_content(&self, content: Content) {
+        *self.content.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(content);
+    }
+}
+
+/// Everything derived from the content root: the posts and what's rendered
+/// from them.
+pub struct Content {
+    blogposts: Vec<BlogPost>,
+    content_errors: Vec<String>,
+    redirects: Redirects,
+    pages: PageCache,
+    babbler: Babbler,
+    /// When the next scheduled post goes live, see [`Content::load`].
+    next_publish: Option<DateTime<Utc>>,
+}
+
+impl Content {
+    /// Loads the content under `path_prefix`, rendering only the posts that
+    /// changed since `previous`. Drafts and scheduled posts are only served
+    /// with `unpublished`, for previewing them. Otherwise the content has to
+    /// be loaded again once [`Content::next_publish`] has passed.
+    #[tracing::instrument(name = "load_content", skip_all)]
+    async fn load(
+        path_prefix: &Path,
+        config: &Config,
+        metrics: &Metrics,
+        previous: Option<&Content>,
+        unpublished: bool,
+    ) -> Result<Self> {
+        let cache = Arc::new(post::render_cache(config.render_cache_dir()));
+        let posts = previous.map_or(&[][..], |content| &content.blogposts);
+        let (mut blogposts, content_errors) =
+            post::reload_posts(&path_prefix.join("blog"), cache.clone(), metrics, posts).await?;
+        // only a full load looks up every post, a reload just the changed ones
+        if previous.is_none() {
+            let pruned = tokio::task::spawn_blocking(move || cache.prune()).await?;
+            if pruned > 0 {
+                tracing::info!("pruned {} stale render cache entry(s)", pruned);
+            }
+        }
+        let next_publish = blogposts
+            .iter()
+            .filter(|post| !unpublished && post.state() == post::PostState::Scheduled)
+            .map(|post| post.date)
+            .min();
+        blogposts.retain(|post| {
+            let published = unpublished || post.is_published();
+            if !published {
+                tracing::info!("not serving {} post {}", post.state(), post.url);
+            }
+            published
+        });
+
+        let redirects = Redirects::load(&path_prefix.join(&config.redirects), &blogposts)?;
+
+        let babbler = Babbler::train(&blogposts);
+
+        let start_time = Instant::now();
+        let pages = PageCache::build(&blogposts, config);
+        tracing::info!(
+            "rendered page cache in {} ms",
+            start_time.elapsed().as_millis()
+        );
+
+        Ok(Self {
+            blogposts,
+            content_errors,
+            redirects,
+            pages,
+            babbler,
+            next_publish,
+        })
+    }
+
+    /// Every published post, newest first, archived ones included. In dev
+    /// mode drafts and scheduled posts too.
+    pub fn posts(&self) -> &[BlogPost] {
+        &self.blogposts
+    }
+
+    /// The post served at `/blog/{url}`.
+    pub fn post(&self, url: &str) -> Option<&BlogPost> {
+        self.blogposts.iter().find(|p| p.url == url)
+    }
+
+    /// Why posts failed to load, if any did.
+    pub fn errors(&self) -> &[String] {
+        &self.content_errors
+    }
+
+    /// The date of the earliest scheduled post, which isn't served yet.
+    pub fn next_publish(&self) -> Option<DateTime<Utc>> {
+        self.next_publish
+    }
+
+    /// The year the pages were rendered in, see [`PageCache::year`].
+    pub fn year(&self) -> i32 {
+        self.pages.year()
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/templates.rs"));
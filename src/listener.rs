@@ -0,0 +1,358 @@
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::os::fd::{FromRawFd, IntoRawFd, RawFd};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use axum::extract::connect_info::Connected;
+use color_eyre::eyre::{eyre, Result};
+use futures_util::{future::BoxFuture, stream::FuturesUnordered, StreamExt};
+use hyper::server::accept::Accept;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::time::Sleep;
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+
+/// First file descriptor passed by systemd socket activation.
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// Clients that haven't finished the TLS handshake by then are dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to stop accepting after an error like running out of file
+/// descriptors, giving open connections a chance to close.
+const ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Where the server accepts connections: a TCP address, a Unix domain socket
+/// or a socket handed over by systemd.
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener, Option<PathBuf>),
+}
+
+impl Listener {
+    /// Takes the socket from systemd when started through socket activation,
+    /// otherwise binds `listen`, which is either `host:port` or `unix:/path`.
+    pub async fn open(listen: &str) -> Result<Self> {
+        match Self::from_systemd()? {
+            Some(listener) => Ok(listener),
+            None => Self::bind(listen).await,
+        }
+    }
+
+    /// Binds `listen`, either `host:port` or `unix:/path`.
+    pub async fn bind(listen: &str) -> Result<Self> {
+        if let Some(path) = listen.strip_prefix("unix:") {
+            let path = PathBuf::from(path);
+            // a socket left behind by an unclean exit would make bind fail
+            match std::fs::remove_file(&path) {
+                Ok(()) => tracing::info!("removed stale socket {}", path.display()),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+            let listener = UnixListener::bind(&path)?;
+            tracing::info!("listening on {}", path.display());
+            return Ok(Listener::Unix(listener, Some(path)));
+        }
+
+        let addr: SocketAddr = listen
+            .parse()
+            .map_err(|err| eyre!(format!("Error parsing listen address ({listen}): {err}")))?;
+        let listener = TcpListener::bind(addr).await?;
+        tracing::info!("listening on {}", addr);
+        Ok(Listener::Tcp(listener))
+    }
+
+    /// Implements the receiving end of `sd_listen_fds(3)` for a single socket.
+    fn from_systemd() -> Result<Option<Self>> {
+        let ours = std::env::var("LISTEN_PID")
+            .ok()
+            .and_then(|pid| pid.parse::<u32>().ok())
+            .is_some_and(|pid| pid == std::process::id());
+        let fds = std::env::var("LISTEN_FDS")
+            .ok()
+            .and_then(|fds| fds.parse::<RawFd>().ok())
+            .unwrap_or(0);
+        if !ours || fds < 1 {
+            return Ok(None);
+        }
+        if fds > 1 {
+            tracing::warn!("got {} sockets from systemd, only using the first", fds);
+        }
+        // so child processes don't think the sockets are theirs
+        std::env::remove_var("LISTEN_PID");
+        std::env::remove_var("LISTEN_FDS");
+        std::env::remove_var("LISTEN_FDNAMES");
+
+        // SAFETY: systemd passes the listening sockets starting at fd 3 and
+        // nothing else in the process has touched them.
+        let tcp = unsafe { std::net::TcpListener::from_raw_fd(SD_LISTEN_FDS_START) };
+        // std can only describe inet addresses, so this fails for Unix sockets
+        if let Ok(addr) = tcp.local_addr() {
+            tcp.set_nonblocking(true)?;
+            tracing::info!("listening on {} (systemd)", addr);
+            return Ok(Some(Listener::Tcp(TcpListener::from_std(tcp)?)));
+        }
+
+        // SAFETY: same descriptor, ownership moves over from `tcp`.
+        let unix = unsafe { std::os::unix::net::UnixListener::from_raw_fd(tcp.into_raw_fd()) };
+        let addr = unix
+            .local_addr()
+            .map_err(|err| eyre!(format!("Error using socket passed by systemd: {err}")))?;
+        unix.set_nonblocking(true)?;
+        match addr.as_pathname() {
+            Some(path) => tracing::info!("listening on {} (systemd)", path.display()),
+            None => tracing::info!("listening on unix socket (systemd)"),
+        }
+        // systemd owns the socket file, so it isn't removed on shutdown
+        Ok(Some(Listener::Unix(UnixListener::from_std(unix)?, None)))
+    }
+
+    pub fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Connection>> {
+        loop {
+            let result = match self {
+                Listener::Tcp(listener) => ready!(listener.poll_accept(cx)).map(|(stream, _)| {
+                    let _ = stream.set_nodelay(true);
+                    Connection::Tcp(stream)
+                }),
+                Listener::Unix(listener, _) => {
+                    ready!(listener.poll_accept(cx)).map(|(stream, _)| Connection::Unix(stream))
+                }
+            };
+
+            match result {
+                // the client went away before we got to it, not our problem
+                Err(err) if is_connection_error(&err) => continue,
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, Some(path)) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+fn is_connection_error(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+    )
+}
+
+type Handshake = BoxFuture<'static, io::Result<TlsStream<TcpStream>>>;
+
+/// Connections from a [`Listener`], with TCP ones going through a TLS
+/// handshake first when `tls` is set. Handshakes run concurrently, so a slow
+/// client doesn't hold up everyone queued behind it. Accept errors are
+/// logged and retried after a pause instead of ending the server.
+pub struct Incoming {
+    listener: Listener,
+    tls: Option<TlsAcceptor>,
+    handshakes: FuturesUnordered<Handshake>,
+    backoff: Option<Pin<Box<Sleep>>>,
+}
+
+impl Incoming {
+    pub fn new(listener: Listener, tls: Option<TlsAcceptor>) -> Self {
+        Self {
+            listener,
+            tls,
+            handshakes: FuturesUnordered::new(),
+            backoff: None,
+        }
+    }
+}
+
+impl Accept for Incoming {
+    type Conn = Connection;
+    type Error = io::Error;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<io::Result<Connection>>> {
+        let this = self.get_mut();
+
+        if let Some(backoff) = &mut this.backoff {
+            if backoff.as_mut().poll(cx).is_ready() {
+                this.backoff = None;
+            }
+        }
+
+        while this.backoff.is_none() {
+            let Poll::Ready(result) = this.listener.poll_accept(cx) else {
+                break;
+            };
+            match (result, &this.tls) {
+                (Ok(Connection::Tcp(stream)), Some(acceptor)) => {
+                    let accept = acceptor.accept(stream);
+                    this.handshakes.push(Box::pin(async move {
+                        tokio::time::timeout(HANDSHAKE_TIMEOUT, accept)
+                            .await
+                            .map_err(|_| {
+                                io::Error::new(io::ErrorKind::TimedOut, "handshake timed out")
+                            })?
+                    }));
+                }
+                (Ok(connection), _) => return Poll::Ready(Some(Ok(connection))),
+                (Err(err), _) => {
+                    tracing::error!("accept failed, retrying in {:?}: {}", ACCEPT_BACKOFF, err);
+                    let mut backoff = Box::pin(tokio::time::sleep(ACCEPT_BACKOFF));
+                    // registers the wakeup for when it's over
+                    let _ = backoff.as_mut().poll(cx);
+                    this.backoff = Some(backoff);
+                }
+            }
+        }
+
+        while let Poll::Ready(Some(result)) = this.handshakes.poll_next_unpin(cx) {
+            match result {
+                Ok(stream) => return Poll::Ready(Some(Ok(Connection::Tls(Box::new(stream))))),
+                Err(err) => tracing::debug!("tls handshake failed: {}", err),
+            }
+        }
+
+        Poll::Pending
+    }
+}
+
+/// An accepted connection from any [`Listener`].
+pub enum Connection {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+/// Where a connection came from. Unix socket peers have no address, they
+/// are local by definition and show up as `127.0.0.1`, which lets a proxy in
+/// front be trusted like one on loopback. `unix` tells them apart from real
+/// loopback clients, since anyone who can reach the socket looks the same.
+#[derive(Clone, Copy, Debug)]
+pub struct Peer {
+    pub ip: IpAddr,
+    /// Whether the connection is TLS terminated here.
+    pub secure: bool,
+    /// Whether the connection came in over a Unix domain socket.
+    pub unix: bool,
+}
+
+impl Connected<&Connection> for Peer {
+    fn connect_info(connection: &Connection) -> Self {
+        let tcp_peer = |stream: &TcpStream| {
+            stream
+                .peer_addr()
+                .map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |addr| addr.ip())
+        };
+        match connection {
+            Connection::Tcp(stream) => Peer {
+                ip: tcp_peer(stream),
+                secure: false,
+                unix: false,
+            },
+            Connection::Unix(_) => Peer {
+                ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                secure: false,
+                unix: true,
+            },
+            Connection::Tls(stream) => Peer {
+                ip: tcp_peer(stream.get_ref().0),
+                secure: true,
+                unix: false,
+            },
+        }
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Connection::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            Connection::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+            Connection::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Connection::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            Connection::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+            Connection::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Connection::Tcp(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            Connection::Unix(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            Connection::Tls(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match self {
+            Connection::Tcp(stream) => stream.is_write_vectored(),
+            Connection::Unix(stream) => stream.is_write_vectored(),
+            Connection::Tls(stream) => stream.is_write_vectored(),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Connection::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            Connection::Unix(stream) => Pin::new(stream).poll_flush(cx),
+            Connection::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Connection::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            Connection::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+            Connection::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Resolves on SIGTERM (what systemd sends) or SIGINT.
+pub async fn shutdown_signal() {
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                tracing::error!("failed installing SIGTERM handler: {}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate => {}
+    }
+}
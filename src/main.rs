@@ -1,291 +1,307 @@
-use axum::{routing::get, Router};
-use color_eyre::{eyre::eyre, eyre::Result, Report};
-use comrak::plugins::syntect::SyntectAdapter;
-use comrak::{markdown_to_html_with_plugins, Options, Plugins};
-use nom::{
-    branch::alt,
-    bytes::complete::{tag, take_until},
-    combinator::{map, rest},
-    multi::many0,
-    sequence::delimited,
-    IResult,
-};
-
-use rand::prelude::*;
-use rayon::prelude::*;
-use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
-use std::net::SocketAddr;
+use clap::{Parser, Subcommand};
+use color_eyre::eyre::{eyre, Result};
+use std::io::{ErrorKind, Write};
+use std::net::{IpAddr, SocketAddr};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::{sync::RwLock, time::Instant};
+use tokio::time::Duration;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use tracing_subscriber::{prelude::__tracing_subscriber_SubscriberExt, util::SubscriberInitExt};
 
-use chrono::{offset::TimeZone, DateTime, NaiveDate, Utc};
-
-use tower_http::{services::ServeDir, services::ServeFile, trace::TraceLayer};
-
-pub mod handlers;
-use handlers::{
-    handle_404, handle_blog, handle_rss, handle_sitemap, handle_stats, handle_tag, root,
-};
+use site::config::{Config, LogFormat};
+use site::listener::{self, Incoming, Listener, Peer};
+use site::metrics::Metrics;
+use site::post::{self, DATE_FORMAT};
+use site::redirects::Redirects;
+use site::telemetry::Otlp;
+use site::tls::{self, CertStore};
+use site::Site;
+
+#[derive(Parser)]
+#[command(about = "Vilhelm Bergsøe's personal website and blog")]
+struct Cli {
+    /// Directory holding blog/ and assets/ [default: $SITE_ROOT or ./]
+    #[arg(long, global = true)]
+    root: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
 
-pub mod fragments;
+#[derive(Subcommand)]
+enum Command {
+    /// Serve the site, the default without a command
+    Serve {
+        /// Port to listen on, instead of the one in `listen`
+        #[arg(long)]
+        port: Option<u16>,
+        /// Address to listen on, instead of the one in `listen`
+        #[arg(long)]
+        bind: Option<IpAddr>,
+        /// Serve drafts, and reload open pages when the content changes
+        #[arg(long)]
+        dev: bool,
+    },
+    /// Scaffold a draft post dated today
+    New {
+        /// File name of the post, and its url
+        slug: String,
+    },
+    /// Validate all content, exiting non-zero on errors
+    Check,
+    /// List every post with its state and word count
+    List,
+    /// Render the site into static files
+    Export {
+        /// Directory to write to, cleared first if it holds a previous export
+        #[arg(long)]
+        out: PathBuf,
+        /// Path the site is hosted under, put in front of every link
+        #[arg(long, default_value = "/")]
+        base_path: String,
+    },
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
     color_eyre::install()?;
+    let cli = Cli::parse();
+
+    // gets SITE_ROOT env var used for nix deployment
+    let site_root = match cli.root {
+        Some(root) => root,
+        None => PathBuf::from(std::env::var("SITE_ROOT").unwrap_or_else(|_| "./".to_string())),
+    };
+    let path_prefix = site_root.as_path();
+
+    let mut config = Config::load(path_prefix)?;
+
+    // authoring commands print their own output, logs stay out of the way
+    let authoring = matches!(
+        cli.command,
+        Some(Command::New { .. } | Command::Check | Command::List)
+    );
+    let (default_filter, writer) = if authoring {
+        ("site=warn", BoxMakeWriter::new(std::io::stderr))
+    } else {
+        ("site=info,tower_http=info", BoxMakeWriter::new(std::io::stdout))
+    };
+    let (text, json) = match config.log.format {
+        LogFormat::Text => (Some(tracing_subscriber::fmt::layer().with_writer(writer)), None),
+        LogFormat::Json => (None, Some(tracing_subscriber::fmt::layer().json().with_writer(writer))),
+    };
+    let (otlp, otel) = match &config.log.otlp {
+        Some(otlp) => {
+            let (otlp, layer) = Otlp::new(otlp)?;
+            (Some(otlp), Some(layer))
+        }
+        None => (None, None),
+    };
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "site=info,tower_http=info".into()),
+                .unwrap_or_else(|_| default_filter.into()),
         )
-        .with(tracing_subscriber::fmt::layer())
+        .with(text)
+        .with(json)
+        .with(otel)
         .init();
 
-    // gets SITE_ROOT env var used for nix deployment
-    let site_root = std::env::var("SITE_ROOT").unwrap_or_else(|_| "./".to_string());
-    let path_prefix = Path::new(&site_root);
-
     tracing::info!("site root: {}", path_prefix.display());
+    let config_path = Config::path(path_prefix);
+    if config_path.exists() {
+        tracing::info!("config: {}", config_path.display());
+    } else {
+        tracing::info!("no config at {}, using defaults", config_path.display());
+    }
 
-    let state = new_state(path_prefix).await?;
-
-    let app = Router::new()
-        .route("/", get(root))
-        .route("/blog/:url", get(handle_blog))
-        .route("/tag/:tag", get(handle_tag))
-        .route("/stats", get(handle_stats))
-        .route("/sitemap.xml", get(handle_sitemap))
-        .route("/rss.xml", get(handle_rss))
-        .route_service(
-            "/robots.txt",
-            ServeFile::new(path_prefix.join(Path::new("assets/robots.txt"))),
-        )
-        .with_state(state.into())
-        .nest_service(
-            "/assets",
-            ServeDir::new(path_prefix.join(Path::new("assets"))),
-        )
-        .fallback(get(handle_404));
-
-    let addr = SocketAddr::from(([0, 0, 0, 0], 8080));
-    tracing::info!("listening on {}", addr);
-    axum::Server::bind(&addr)
-        .serve(
-            app.layer(TraceLayer::new_for_http())
-                .into_make_service_with_connect_info::<SocketAddr>(),
-        )
-        .await?;
-
-    Ok(())
-}
-
-#[derive(Clone)]
-pub struct BlogPost {
-    url: String,
-    title: String,
-    date: DateTime<Utc>,
-    archived: bool,
-    tags: Vec<String>,
-    content: String,
-    estimated_read_time: usize,
-}
-
-pub type UserId = u64;
-
-pub struct State {
-    blogposts: Vec<BlogPost>,
-    uptime: DateTime<Utc>,
-    total_views: RwLock<HashMap<String, HashSet<UserId>>>,
-    salt: u64,
-}
-
-pub type SharedState = Arc<State>;
-
-#[derive(Debug, Deserialize, Serialize)]
-struct Frontmatter {
-    title: String,
-    date: String,
-    archived: bool,
-    tags: Vec<String>,
-}
-
-fn parse_frontmatter(input: &str) -> IResult<&str, &str> {
-    let delimiter = "---";
-
-    let (input, frontmatter) =
-        delimited(tag(delimiter), take_until(delimiter), tag(delimiter))(input)?;
-    let content = input.trim_start();
-
-    Ok((frontmatter, content))
-}
-
-#[derive(Debug)]
-struct MathExpr {
-    display_mode: bool,
-    expr: String,
-}
+    let result = match cli.command {
+        Some(Command::Serve { port, bind, dev }) => {
+            config.listen = listen_address(&config.listen, bind, port);
+            serve(path_prefix, config, dev).await
+        }
+        Some(Command::New { slug }) => new_post(path_prefix, &slug),
+        Some(Command::Check) => check(path_prefix, &config).await,
+        Some(Command::List) => list(path_prefix, &config).await,
+        Some(Command::Export { out, base_path }) => {
+            export(path_prefix, config, &out, &base_path).await
+        }
+        None => serve(path_prefix, config, false).await,
+    };
 
-fn math_expr(input: &str) -> IResult<&str, MathExpr> {
-    let (input, _) = tag("<span data-math-style=\"")(input)?;
-    let (input, style) = take_until("\">")(input)?;
-    let (input, _) = tag("\">")(input)?;
-    let (input, expression) = take_until("</span>")(input)?;
-    let (input, _) = tag("</span>")(input)?;
-    Ok((
-        input,
-        MathExpr {
-            display_mode: style == "display",
-            expr: expression.to_string(),
-        },
-    ))
-}
+    if let Some(otlp) = otlp {
+        otlp.shutdown();
+    }
 
-fn non_math_expr(input: &str) -> IResult<&str, String> {
-    map(take_until("<span data-math-style=\""), |s: &str| {
-        s.to_string()
-    })(input)
+    result
 }
 
-fn parse_math_exprs(input: &str) -> IResult<&str, String> {
-    let (input, parsed) = many0(alt((
-        map(math_expr, |mathexpr| {
-            let opts = katex::Opts::builder()
-                .display_mode(mathexpr.display_mode)
-                .output_type(katex::opts::OutputType::Mathml)
-                .build()
-                .unwrap();
-
-            // Decode HTML entities for katex
-            let decoded_expr = mathexpr
-                .expr
-                .replace("&gt;", ">")
-                .replace("&lt;", "<")
-                .replace("&amp;", "&");
-
-            katex::render_with_opts(&decoded_expr, &opts).unwrap()
-        }),
-        non_math_expr,
-    )))(input)?;
-
-    let (input, remaining) = rest(input)?;
-
-    Ok((input, format!("{}{}", parsed.concat(), remaining)))
+/// `listen` with the address or port swapped out, when given. A unix socket
+/// in `listen` is replaced by the default address.
+fn listen_address(listen: &str, bind: Option<IpAddr>, port: Option<u16>) -> String {
+    if bind.is_none() && port.is_none() {
+        return listen.to_string();
+    }
+    let current = listen
+        .parse::<SocketAddr>()
+        .or_else(|_| Config::default().listen.parse())
+        .unwrap_or_else(|_| SocketAddr::from(([0, 0, 0, 0], 8080)));
+    SocketAddr::new(bind.unwrap_or(current.ip()), port.unwrap_or(current.port())).to_string()
 }
 
-async fn parse_blog(
-    url: &str,
-    path: &PathBuf,
-    options: &Options<'_>,
-    plugins: &Plugins<'_>,
-) -> Result<BlogPost, Report> {
-    let bytes = tokio::fs::read(path).await?;
-    let text = String::from_utf8_lossy(&bytes);
-
-    let Ok((frontmatter, content)) = parse_frontmatter(&text) else {
+fn new_post(path_prefix: &Path, slug: &str) -> Result<()> {
+    let valid = !slug.is_empty()
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
         return Err(eyre!(format!(
-            "Error parsing frontmatter ({url}). Most likely missing delimiter \"---\\n\""
+            "Error creating post ({slug}): slugs may only contain letters, digits, '-' and '_'"
         )));
-    };
-
-    let frontmatter: Frontmatter = match serde_yaml::from_str(frontmatter) {
-        Ok(fm) => fm,
-        Err(err) => return Err(eyre!(format!("Error parsing blog ({url}): {err}"))),
-    };
-
-    let naive_date = NaiveDate::parse_from_str(&frontmatter.date, "%d-%m-%Y").unwrap();
-    let naive_datetime = naive_date.and_hms_opt(0, 0, 0).unwrap();
-    let date: DateTime<Utc> = Utc.from_utc_datetime(&naive_datetime);
-
-    let html = markdown_to_html_with_plugins(content, &options, &plugins);
+    }
 
-    // Parse all math expressions
-    let html = match parse_math_exprs(&html) {
-        Ok((_, parsed)) => parsed,
+    let path = path_prefix.join("blog").join(format!("{slug}.md"));
+    // post dates are UTC days, so today is too
+    let today = chrono::Utc::now().date_naive();
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path);
+    match file {
+        Ok(mut file) => file.write_all(post::scaffold(slug, today).as_bytes())?,
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+            return Err(eyre!(format!(
+                "Error creating post ({}): it already exists",
+                path.display()
+            )));
+        }
         Err(err) => {
             return Err(eyre!(format!(
-                "Error parsing math expressions for blog ({url}): {err}"
+                "Error creating post ({}): {err}",
+                path.display()
             )));
         }
-    };
+    }
 
-    Ok(BlogPost {
-        url: url.to_string(),
-        title: frontmatter.title,
-        date,
-        archived: frontmatter.archived,
-        tags: frontmatter.tags,
-        content: html,
-        estimated_read_time: content.split_whitespace().count() / 200,
-    })
+    println!("created {}", path.display());
+    Ok(())
 }
 
-async fn new_state(path_prefix: &Path) -> Result<SharedState> {
-    let mut blogposts: Vec<BlogPost> = Vec::new();
-
-    let mut blog_dir = match tokio::fs::read_dir(path_prefix.join(Path::new("blog"))).await {
-        Ok(dir) => dir,
-        Err(err) => return Err(eyre!(format!("Error reading blog directory: {err}"))),
-    };
-
-    let adapter = SyntectAdapter::new(None);
-    let mut options = Options::default();
-    let mut plugins = Plugins::default();
-
-    options.extension.strikethrough = true;
-    options.extension.table = true;
-    options.extension.autolink = true;
-    options.extension.footnotes = true;
-    options.extension.header_ids = Some("".to_string());
-    options.extension.math_dollars = true;
-    options.render.unsafe_ = true;
-
-    while let Some(entry) = blog_dir.next_entry().await? {
-        let path = entry.path();
-        if path.is_file() {
-            // check for invalid file extensions
-            let ext = path.extension();
-            if ext != Some(std::ffi::OsStr::new("md"))
-                && ext != Some(std::ffi::OsStr::new("markdown"))
-                || ext.is_none()
-            {
-                tracing::warn!("skipping non markdown file: {}", path.display());
-                continue;
-            }
+async fn check(path_prefix: &Path, config: &Config) -> Result<()> {
+    let (blogposts, mut errors) = post::load_posts(
+        &path_prefix.join("blog"),
+        Arc::new(post::render_cache(config.render_cache_dir())),
+        &Metrics::default(),
+    )
+    .await?;
+    if let Err(err) = Redirects::load(&path_prefix.join(&config.redirects), &blogposts) {
+        errors.push(err.to_string());
+    }
 
-            if let Some(stem) = path.file_stem() {
-                let url = stem.to_str().unwrap();
+    if !errors.is_empty() {
+        for error in &errors {
+            eprintln!("error: {error}");
+        }
+        return Err(eyre!(format!("{} problem(s) found", errors.len())));
+    }
+    println!("{} post(s) ok", blogposts.len());
+    Ok(())
+}
 
-                // check if blogpost exists with same url
-                if blogposts.par_iter().any(|b| b.url == url) {
-                    tracing::warn!("skipping duplicate blogpost: {}", url);
-                    continue;
-                }
+async fn list(path_prefix: &Path, config: &Config) -> Result<()> {
+    let (blogposts, errors) = post::load_posts(
+        &path_prefix.join("blog"),
+        Arc::new(post::render_cache(config.render_cache_dir())),
+        &Metrics::default(),
+    )
+    .await?;
+    for error in &errors {
+        eprintln!("error: {error}");
+    }
 
-                plugins.render.codefence_syntax_highlighter = Some(&adapter);
+    let width = blogposts.iter().map(|p| p.url().len()).max().unwrap_or(0);
+    let mut out = std::io::stdout().lock();
+    for post in &blogposts {
+        let line = writeln!(
+            out,
+            "{:<9}  {}  {:>6} words  {:<width$}  {}",
+            post.state(),
+            post.date().format(DATE_FORMAT),
+            post.word_count(),
+            post.url(),
+            post.title(),
+        );
+        // stdout went away, e.g. piped into head
+        if line.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
 
-                let start_time = Instant::now();
-                let blogpost = parse_blog(url, &path, &options, &plugins).await?;
-                let elapsed = start_time.elapsed().as_millis();
+async fn export(path_prefix: &Path, config: Config, out: &Path, base_path: &str) -> Result<()> {
+    let written = Site::new(path_prefix)
+        .config(config)
+        .export(out, base_path)
+        .await?;
+    tracing::info!("exported {} file(s) to {}", written, out.display());
+    Ok(())
+}
 
-                blogposts.push(blogpost);
-                tracing::info!("loaded blogpost - {} in {} ms", url, elapsed);
+async fn serve(path_prefix: &Path, config: Config, dev: bool) -> Result<()> {
+    let (app, site) = Site::new(path_prefix)
+        .config(config.clone())
+        .dev(dev)
+        .build()
+        .await?;
+    site.start().await?;
+
+    let listener = Listener::open(&config.listen).await?;
+    let tls = match &config.tls {
+        Some(tls) => {
+            let certs = Arc::new(CertStore::open(tls)?);
+            certs.watch(
+                Duration::from_secs(tls.reload_interval_secs.max(1)),
+                site.metrics(),
+            );
+            if let Some(redirect) = tls.redirect_http.clone() {
+                let https_port = config
+                    .listen
+                    .parse::<std::net::SocketAddr>()
+                    .map_or(443, |addr| addr.port());
+                tokio::spawn(async move {
+                    if let Err(err) = tls::serve_redirect(redirect, https_port).await {
+                        tracing::error!("http redirect listener failed: {}", err);
+                    }
+                });
+            }
+            Some(certs.acceptor()?)
+        }
+        None => None,
+    };
+    let incoming = Incoming::new(listener, tls);
+    let (drain_tx, drain_rx) = tokio::sync::oneshot::channel::<()>();
+    let server = axum::Server::builder(incoming)
+        .serve(app.into_make_service_with_connect_info::<Peer>())
+        .with_graceful_shutdown(async {
+            let _ = drain_rx.await;
+        });
+    tokio::pin!(server);
+
+    tokio::select! {
+        result = &mut server => result?,
+        _ = listener::shutdown_signal() => {
+            let drain_timeout = Duration::from_secs(config.drain_timeout_secs);
+            tracing::info!("shutting down, draining connections for up to {:?}", drain_timeout);
+            let _ = drain_tx.send(());
+            site.close_streams();
+            match tokio::time::timeout(drain_timeout, &mut server).await {
+                Ok(result) => result?,
+                Err(_) => tracing::warn!("drain timeout reached, dropping remaining connections"),
             }
         }
     }
 
-    let salt = rand::rng().random::<u64>();
-    tracing::info!("Generated server salt for this session");
-
-    blogposts.sort_by(|a, b| b.date.cmp(&a.date));
+    site.flush();
 
-    Ok(Arc::new(State {
-        blogposts,
-        uptime: chrono::Utc::now(),
-        total_views: RwLock::new(HashMap::new()),
-        salt,
-    }))
+    Ok(())
 }
-
-include!(concat!(env!("OUT_DIR"), "/templates.rs"));
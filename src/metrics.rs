@@ -0,0 +1,317 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{MatchedPath, State},
+    http::Request,
+    middleware::Next,
+    response::Response,
+};
+use comrak::adapters::SyntaxHighlighterAdapter;
+
+use crate::SharedState;
+
+/// Upper bounds of the request latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0,
+];
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        for (bucket, le) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= le {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+/// Where the time rendering one post went.
+#[derive(Clone, Copy, Default)]
+pub struct RenderTimings {
+    /// All of `parse_blog`, including the two below.
+    pub total: Duration,
+    pub katex: Duration,
+    pub syntect: Duration,
+}
+
+/// Wraps the syntax highlighter to time it, since it runs deep inside
+/// comrak. One per post, so the time can be attributed.
+pub struct TimedHighlighter<'a> {
+    inner: &'a dyn SyntaxHighlighterAdapter,
+    nanos: AtomicU64,
+}
+
+impl<'a> TimedHighlighter<'a> {
+    pub fn new(inner: &'a dyn SyntaxHighlighterAdapter) -> Self {
+        Self {
+            inner,
+            nanos: AtomicU64::new(0),
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::Relaxed))
+    }
+}
+
+impl SyntaxHighlighterAdapter for TimedHighlighter<'_> {
+    fn write_highlighted(
+        &self,
+        output: &mut dyn io::Write,
+        lang: Option<&str>,
+        code: &str,
+    ) -> io::Result<()> {
+        let _span = tracing::info_span!("syntect", lang = lang.unwrap_or("")).entered();
+        let start = Instant::now();
+        let result = self.inner.write_highlighted(output, lang, code);
+        self.nanos
+            .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        result
+    }
+
+    fn write_pre_tag(
+        &self,
+        output: &mut dyn io::Write,
+        attributes: std::collections::HashMap<String, String>,
+    ) -> io::Result<()> {
+        self.inner.write_pre_tag(output, attributes)
+    }
+
+    fn write_code_tag(
+        &self,
+        output: &mut dyn io::Write,
+        attributes: std::collections::HashMap<String, String>,
+    ) -> io::Result<()> {
+        self.inner.write_code_tag(output, attributes)
+    }
+}
+
+/// Counters behind `/metrics`, rendered in the Prometheus text format.
+#[derive(Default)]
+pub struct Metrics {
+    requests: Mutex<BTreeMap<(String, u16), Histogram>>,
+    renders: Mutex<BTreeMap<String, RenderTimings>>,
+    reloads: Mutex<BTreeMap<(&'static str, &'static str), u64>>,
+}
+
+impl Metrics {
+    pub fn record_request(&self, route: String, status: u16, elapsed: Duration) {
+        self.requests
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry((route, status))
+            .or_default()
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn record_render(&self, url: &str, timings: RenderTimings) {
+        self.renders
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(url.to_string(), timings);
+    }
+
+    /// Counts a reload of `kind` (content, certificate) by outcome.
+    pub fn record_reload(&self, kind: &'static str, ok: bool) {
+        let outcome = if ok { "success" } else { "failure" };
+        *self
+            .reloads
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry((kind, outcome))
+            .or_default() += 1;
+    }
+}
+
+/// Escapes a label value as the text format wants it.
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+pub async fn render(state: &SharedState) -> String {
+    let metrics = &state.metrics;
+    let mut out = String::new();
+
+    {
+        let requests = metrics.requests.lock().unwrap_or_else(|e| e.into_inner());
+
+        family(
+            &mut out,
+            "site_http_requests_total",
+            "counter",
+            "Requests served, by route and status.",
+        );
+        for ((route, status), histogram) in requests.iter() {
+            let _ = writeln!(
+                out,
+                "site_http_requests_total{{route=\"{}\",status=\"{status}\"}} {}",
+                label(route),
+                histogram.count
+            );
+        }
+
+        family(
+            &mut out,
+            "site_http_request_duration_seconds",
+            "histogram",
+            "Time until the response headers were ready, by route and status.",
+        );
+        for ((route, status), histogram) in requests.iter() {
+            let labels = format!("route=\"{}\",status=\"{status}\"", label(route));
+            for (count, le) in histogram.buckets.iter().zip(LATENCY_BUCKETS) {
+                let _ = writeln!(
+                    out,
+                    "site_http_request_duration_seconds_bucket{{{labels},le=\"{le}\"}} {count}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "site_http_request_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {}",
+                histogram.count
+            );
+            let _ = writeln!(
+                out,
+                "site_http_request_duration_seconds_sum{{{labels}}} {}",
+                histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "site_http_request_duration_seconds_count{{{labels}}} {}",
+                histogram.count
+            );
+        }
+    }
+
+    family(
+        &mut out,
+        "site_posts_loaded",
+        "gauge",
+        "Blog posts currently served.",
+    );
+    let _ = writeln!(out, "site_posts_loaded {}", state.content().blogposts.len());
+
+    family(
+        &mut out,
+        "site_content_errors",
+        "gauge",
+        "Posts that failed to load.",
+    );
+    let _ = writeln!(out, "site_content_errors {}", state.content().content_errors.len());
+
+    family(
+        &mut out,
+        "site_post_render_seconds",
+        "gauge",
+        "Time spent rendering each post, by stage.",
+    );
+    for (url, timings) in metrics
+        .renders
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+    {
+        let url = label(url);
+        for (stage, duration) in [
+            ("parse_blog", timings.total),
+            ("katex", timings.katex),
+            ("syntect", timings.syntect),
+        ] {
+            let _ = writeln!(
+                out,
+                "site_post_render_seconds{{post=\"{url}\",stage=\"{stage}\"}} {}",
+                duration.as_secs_f64()
+            );
+        }
+    }
+
+    family(
+        &mut out,
+        "site_reloads_total",
+        "counter",
+        "Reloads, by what was reloaded and outcome.",
+    );
+    for ((kind, outcome), count) in metrics
+        .reloads
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+    {
+        let _ = writeln!(
+            out,
+            "site_reloads_total{{kind=\"{kind}\",outcome=\"{outcome}\"}} {count}"
+        );
+    }
+
+    family(
+        &mut out,
+        "site_post_views",
+        "gauge",
+        "Unique views per post.",
+    );
+    let views = state.total_views.read().await;
+    let mut views: Vec<_> = views
+        .iter()
+        .map(|(url, views)| (url, views.count()))
+        .collect();
+    views.sort();
+    for (url, count) in views {
+        let _ = writeln!(out, "site_post_views{{post=\"{}\"}} {count}", label(url));
+    }
+
+    family(
+        &mut out,
+        "site_uptime_seconds",
+        "gauge",
+        "Seconds since the server started.",
+    );
+    let _ = writeln!(
+        out,
+        "site_uptime_seconds {}",
+        (chrono::Utc::now() - state.uptime).num_seconds()
+    );
+
+    out
+}
+
+/// Middleware timing every request under the route it matched, rather than
+/// its raw path, so the label set stays bounded.
+pub async fn track<B>(
+    State(state): State<SharedState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let route = match request.extensions().get::<MatchedPath>() {
+        Some(path) => path
+            .as_str()
+            .replace("/*__private__axum_nest_tail_param", "/*"),
+        None => "fallback".to_string(),
+    };
+    let start = Instant::now();
+    let response = next.run(request).await;
+    state
+        .metrics
+        .record_request(route, response.status().as_u16(), start.elapsed());
+    response
+}
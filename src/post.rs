@@ -0,0 +1,511 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{offset::TimeZone, DateTime, NaiveDate, Utc};
+use color_eyre::{eyre::eyre, eyre::Result, Report};
+use comrak::adapters::SyntaxHighlighterAdapter;
+use comrak::plugins::syntect::SyntectAdapter;
+use comrak::{markdown_to_html_with_plugins, Options, Plugins};
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_until},
+    combinator::{map, rest},
+    multi::many0,
+    sequence::delimited,
+    IResult,
+};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+use crate::metrics::{Metrics, RenderTimings, TimedHighlighter};
+use crate::render_cache::RenderCache;
+
+#[derive(Clone)]
+pub struct BlogPost {
+    pub(crate) url: String,
+    pub(crate) title: String,
+    pub(crate) date: DateTime<Utc>,
+    /// When the post was last revised, from its frontmatter.
+    pub(crate) updated: Option<DateTime<Utc>>,
+    /// Modification time of the post's file.
+    pub(crate) modified: Option<DateTime<Utc>>,
+    pub(crate) archived: bool,
+    pub(crate) draft: bool,
+    pub(crate) tags: Vec<String>,
+    pub(crate) aliases: Vec<String>,
+    pub(crate) content: String,
+    pub(crate) markdown: String,
+    pub(crate) estimated_read_time: usize,
+}
+
+impl BlogPost {
+    /// The slug the post is served under, `/blog/{url}`.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn date(&self) -> DateTime<Utc> {
+        self.date
+    }
+
+    /// The `updated` date from the frontmatter, if any.
+    pub fn updated(&self) -> Option<DateTime<Utc>> {
+        self.updated
+    }
+
+    /// When the post last changed: its `updated` date if it has one, else
+    /// the file's modification time. Never before the post date, since
+    /// checkouts and the nix store don't keep meaningful mtimes.
+    pub fn last_modified(&self) -> DateTime<Utc> {
+        self.updated
+            .unwrap_or_else(|| self.modified.map_or(self.date, |m| m.max(self.date)))
+    }
+
+    /// Archived posts are served, but left out of listings.
+    pub fn archived(&self) -> bool {
+        self.archived
+    }
+
+    pub fn draft(&self) -> bool {
+        self.draft
+    }
+
+    pub fn state(&self) -> PostState {
+        if self.draft {
+            PostState::Draft
+        } else if self.date > Utc::now() {
+            PostState::Scheduled
+        } else if self.archived {
+            PostState::Archived
+        } else {
+            PostState::Published
+        }
+    }
+
+    /// Whether the post is served: it's not a draft, and its date has come.
+    pub fn is_published(&self) -> bool {
+        matches!(self.state(), PostState::Published | PostState::Archived)
+    }
+
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    /// Old urls redirected to the post.
+    pub fn aliases(&self) -> &[String] {
+        &self.aliases
+    }
+
+    /// The rendered body, as HTML.
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    /// The markdown source, without frontmatter.
+    pub fn markdown(&self) -> &str {
+        &self.markdown
+    }
+
+    /// Reading time in minutes.
+    pub fn estimated_read_time(&self) -> usize {
+        self.estimated_read_time
+    }
+
+    pub fn word_count(&self) -> usize {
+        self.markdown.split_whitespace().count()
+    }
+}
+
+/// Where a post is in its life. Drafts and scheduled posts are loaded, but
+/// only served once published.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PostState {
+    Published,
+    Archived,
+    Draft,
+    /// Dated in the future, served from that day on. A running server
+    /// reloads its content when the day comes.
+    Scheduled,
+}
+
+impl std::fmt::Display for PostState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            PostState::Published => "published",
+            PostState::Archived => "archived",
+            PostState::Draft => "draft",
+            PostState::Scheduled => "scheduled",
+        })
+    }
+}
+
+/// How dates are written in the frontmatter.
+pub const DATE_FORMAT: &str = "%d-%m-%Y";
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Frontmatter {
+    title: String,
+    date: String,
+    /// Date of the last real revision, in [`DATE_FORMAT`] like `date`.
+    #[serde(default)]
+    updated: Option<String>,
+    archived: bool,
+    /// Drafts are checked and listed, but not served.
+    #[serde(default)]
+    draft: bool,
+    tags: Vec<String>,
+    /// Old urls of the post, either slugs or paths, redirected to it.
+    #[serde(default)]
+    aliases: Vec<String>,
+}
+
+/// A new post's source: frontmatter for a draft dated `date`, titled after
+/// its slug, and an empty body.
+pub fn scaffold(slug: &str, date: NaiveDate) -> String {
+    let words = slug.replace(['-', '_'], " ");
+    let mut chars = words.chars();
+    let title: String = match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    };
+    format!(
+        "---\ntitle: {title:?}\ndate: {}\narchived: false\ndraft: true\ntags: []\n---\n\n",
+        date.format(DATE_FORMAT)
+    )
+}
+
+fn parse_frontmatter(input: &str) -> IResult<&str, &str> {
+    let delimiter = "---";
+
+    let (input, frontmatter) =
+        delimited(tag(delimiter), take_until(delimiter), tag(delimiter))(input)?;
+    let content = input.trim_start();
+
+    Ok((frontmatter, content))
+}
+
+#[derive(Debug)]
+struct MathExpr {
+    display_mode: bool,
+    expr: String,
+}
+
+fn math_expr(input: &str) -> IResult<&str, MathExpr> {
+    let (input, _) = tag("<span data-math-style=\"")(input)?;
+    let (input, style) = take_until("\">")(input)?;
+    let (input, _) = tag("\">")(input)?;
+    let (input, expression) = take_until("</span>")(input)?;
+    let (input, _) = tag("</span>")(input)?;
+    Ok((
+        input,
+        MathExpr {
+            display_mode: style == "display",
+            expr: expression.to_string(),
+        },
+    ))
+}
+
+impl MathExpr {
+    fn render(&self) -> Result<String> {
+        let opts = katex::Opts::builder()
+            .display_mode(self.display_mode)
+            .output_type(katex::opts::OutputType::Mathml)
+            .build()
+            .unwrap();
+
+        // Decode HTML entities for katex
+        let decoded_expr = self
+            .expr
+            .replace("&gt;", ">")
+            .replace("&lt;", "<")
+            .replace("&amp;", "&");
+
+        katex::render_with_opts(&decoded_expr, &opts)
+            .map_err(|err| eyre!(format!("Error rendering math ({decoded_expr}): {err}")))
+    }
+}
+
+enum Piece {
+    Text(String),
+    Math(MathExpr),
+}
+
+fn non_math_expr(input: &str) -> IResult<&str, Piece> {
+    map(take_until("<span data-math-style=\""), |s: &str| {
+        Piece::Text(s.to_string())
+    })(input)
+}
+
+fn parse_math_exprs(input: &str) -> IResult<&str, Vec<Piece>> {
+    let (input, mut parsed) = many0(alt((map(math_expr, Piece::Math), non_math_expr)))(input)?;
+
+    let (input, remaining) = rest(input)?;
+    parsed.push(Piece::Text(remaining.to_string()));
+
+    Ok((input, parsed))
+}
+
+/// Renders the math comrak marked up in `html` with KaTeX.
+fn render_math(html: &str) -> Result<String> {
+    let (_, pieces) = parse_math_exprs(html).map_err(|err| eyre!(err.to_string()))?;
+
+    let mut out = String::with_capacity(html.len());
+    for piece in pieces {
+        match piece {
+            Piece::Text(text) => out.push_str(&text),
+            Piece::Math(expr) => out.push_str(&expr.render()?),
+        }
+    }
+    Ok(out)
+}
+
+/// Modification time of the file at `path`.
+fn modified(path: &Path) -> Option<DateTime<Utc>> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .map(DateTime::<Utc>::from)
+}
+
+fn render_options() -> Options<'static> {
+    let mut options = Options::default();
+    options.extension.strikethrough = true;
+    options.extension.table = true;
+    options.extension.autolink = true;
+    options.extension.footnotes = true;
+    options.extension.header_ids = Some("".to_string());
+    options.extension.math_dollars = true;
+    options.render.unsafe_ = true;
+    options
+}
+
+#[tracing::instrument(skip_all, fields(post = url))]
+fn parse_blog(
+    url: &str,
+    path: &Path,
+    options: &Options<'_>,
+    highlighter: &dyn SyntaxHighlighterAdapter,
+    cache: &RenderCache,
+) -> Result<(BlogPost, RenderTimings), Report> {
+    let start_time = Instant::now();
+    let highlighter = TimedHighlighter::new(highlighter);
+    let mut plugins = Plugins::default();
+    plugins.render.codefence_syntax_highlighter = Some(&highlighter);
+
+    let bytes = std::fs::read(path)?;
+    let modified = modified(path);
+    let text = String::from_utf8_lossy(&bytes);
+
+    let frontmatter_span = tracing::info_span!("frontmatter").entered();
+    let Ok((frontmatter, content)) = parse_frontmatter(&text) else {
+        return Err(eyre!(format!(
+            "Error parsing frontmatter ({url}). Most likely missing delimiter \"---\\n\""
+        )));
+    };
+
+    let frontmatter: Frontmatter = match serde_yaml::from_str(frontmatter) {
+        Ok(fm) => fm,
+        Err(err) => return Err(eyre!(format!("Error parsing blog ({url}): {err}"))),
+    };
+    frontmatter_span.exit();
+
+    let naive_date = match NaiveDate::parse_from_str(&frontmatter.date, DATE_FORMAT) {
+        Ok(date) => date,
+        Err(err) => return Err(eyre!(format!("Error parsing date for blog ({url}): {err}"))),
+    };
+    let naive_datetime = naive_date.and_hms_opt(0, 0, 0).unwrap();
+    let date: DateTime<Utc> = Utc.from_utc_datetime(&naive_datetime);
+    let updated = match frontmatter.updated {
+        Some(updated) => match NaiveDate::parse_from_str(&updated, DATE_FORMAT) {
+            Ok(day) => Some(Utc.from_utc_datetime(&day.and_hms_opt(0, 0, 0).unwrap())),
+            Err(err) => {
+                return Err(eyre!(format!(
+                    "Error parsing updated date for blog ({url}): {err}"
+                )));
+            }
+        },
+        None => None,
+    };
+
+    let key = cache.key(content);
+    let (html, katex) = match cache.get(&key) {
+        Some(html) => (html, Duration::ZERO),
+        None => {
+            let html = tracing::info_span!("comrak")
+                .in_scope(|| markdown_to_html_with_plugins(content, options, &plugins));
+
+            // Parse all math expressions
+            let katex_start = Instant::now();
+            let html = tracing::info_span!("katex").in_scope(|| render_math(&html));
+            let katex = katex_start.elapsed();
+            let html = match html {
+                Ok(rendered) => rendered,
+                Err(err) => {
+                    return Err(eyre!(format!(
+                        "Error parsing math expressions for blog ({url}): {err}"
+                    )));
+                }
+            };
+
+            cache.put(&key, &html);
+            (html, katex)
+        }
+    };
+
+    let timings = RenderTimings {
+        total: start_time.elapsed(),
+        katex,
+        syntect: highlighter.elapsed(),
+    };
+
+    let blogpost = BlogPost {
+        url: url.to_string(),
+        title: frontmatter.title,
+        date,
+        updated,
+        modified,
+        archived: frontmatter.archived,
+        draft: frontmatter.draft,
+        tags: frontmatter.tags,
+        aliases: frontmatter.aliases,
+        content: html,
+        markdown: content.to_string(),
+        estimated_read_time: content.split_whitespace().count() / 200,
+    };
+
+    Ok((blogpost, timings))
+}
+
+/// The render cache in `dir`, for the options posts are rendered with.
+pub fn render_cache(dir: PathBuf) -> RenderCache {
+    RenderCache::new(dir, &format!("{:?} syntect=classes", render_options()))
+}
+
+/// Loads every post in `blog_dir`, newest first, drafts and scheduled posts
+/// included. Posts that fail to load are skipped, their errors returned
+/// alongside for `/readyz`. Rendered posts are looked up in and added to
+/// `cache`.
+pub async fn load_posts(
+    blog_dir: &Path,
+    cache: Arc<RenderCache>,
+    metrics: &Metrics,
+) -> Result<(Vec<BlogPost>, Vec<String>)> {
+    reload_posts(blog_dir, cache, metrics, &[]).await
+}
+
+/// Like [`load_posts`], but posts in `previous` whose file hasn't been
+/// modified since are kept instead of rendered again.
+pub async fn reload_posts(
+    blog_dir: &Path,
+    cache: Arc<RenderCache>,
+    metrics: &Metrics,
+    previous: &[BlogPost],
+) -> Result<(Vec<BlogPost>, Vec<String>)> {
+    let mut blog_dir = match tokio::fs::read_dir(blog_dir).await {
+        Ok(dir) => dir,
+        Err(err) => return Err(eyre!(format!("Error reading blog directory: {err}"))),
+    };
+
+    let mut paths = Vec::new();
+    while let Some(entry) = blog_dir.next_entry().await? {
+        let path = entry.path();
+        if path.is_file() {
+            // check for invalid file extensions
+            let ext = path.extension();
+            if ext != Some(std::ffi::OsStr::new("md"))
+                && ext != Some(std::ffi::OsStr::new("markdown"))
+                || ext.is_none()
+            {
+                tracing::warn!("skipping non markdown file: {}", path.display());
+                continue;
+            }
+            paths.push(path);
+        }
+    }
+    // directory order is up to the filesystem, sorted the same post wins
+    // between duplicates everywhere
+    paths.sort();
+
+    // posts that failed to load, reported by /readyz
+    let mut content_errors = Vec::new();
+
+    let mut urls = HashSet::new();
+    let mut sources = Vec::new();
+    for path in paths {
+        let Some(url) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            tracing::warn!("skipping blogpost with invalid name: {}", path.display());
+            continue;
+        };
+
+        // check if blogpost exists with same url
+        if !urls.insert(url.to_string()) {
+            tracing::warn!("skipping duplicate blogpost: {}", url);
+            content_errors.push(format!("duplicate blogpost: {url}"));
+            continue;
+        }
+        sources.push((url.to_string(), path));
+    }
+
+    // reading and rendering blocks, so it runs off the async runtime
+    let previous = previous.to_vec();
+    let sources = Arc::new(sources);
+    // the render spans are children of the caller's, whichever thread they're on
+    let span = tracing::Span::current();
+    let render = {
+        let sources = sources.clone();
+        move || {
+            let adapter = SyntectAdapter::new(None);
+            let options = render_options();
+            sources
+                .par_iter()
+                .map(|(url, path)| {
+                    let modified = modified(path);
+                    let unchanged = previous
+                        .iter()
+                        .find(|b| &b.url == url && modified.is_some() && b.modified == modified);
+                    match unchanged {
+                        Some(blogpost) => Ok((blogpost.clone(), None)),
+                        None => span
+                            .in_scope(|| parse_blog(url, path, &options, &adapter, &cache))
+                            .map(|(blogpost, timings)| (blogpost, Some(timings))),
+                    }
+                })
+                .collect::<Vec<_>>()
+        }
+    };
+    let loaded = tokio::task::spawn_blocking(render).await?;
+    let mut blogposts: Vec<BlogPost> = Vec::with_capacity(loaded.len());
+    for ((url, _), loaded) in sources.iter().zip(loaded) {
+        match loaded {
+            Ok((blogpost, Some(timings))) => {
+                metrics.record_render(url, timings);
+                tracing::info!(
+                    "loaded blogpost - {} in {} ms",
+                    url,
+                    timings.total.as_millis()
+                );
+                blogposts.push(blogpost);
+            }
+            Ok((blogpost, None)) => {
+                tracing::debug!("unchanged blogpost - {}", url);
+                blogposts.push(blogpost);
+            }
+            Err(err) => {
+                tracing::error!("skipping blogpost {}: {}", url, err);
+                content_errors.push(err.to_string());
+            }
+        }
+    }
+
+    // stable, so posts from the same day keep their file order
+    blogposts.sort_by_key(|b| std::cmp::Reverse(b.date));
+
+    Ok((blogposts, content_errors))
+}
@@ -0,0 +1,90 @@
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+use axum::http::{header, HeaderMap};
+use chrono::{NaiveDate, Utc};
+use rand::prelude::*;
+
+use crate::UserId;
+
+/// Pseudonymous visitor id, only comparable with ids from the same day.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Visitor {
+    pub id: UserId,
+    pub day: NaiveDate,
+}
+
+/// A day's visitor id key.
+pub type DailyKey = (NaiveDate, [u8; 32]);
+
+/// Derives visitor ids from a keyed BLAKE3 hash of the client IP and user
+/// agent. The key is random and replaced at UTC midnight, after which the
+/// previous day's ids can't be linked to anyone.
+#[derive(Default)]
+pub struct VisitorIds {
+    key: Mutex<Option<DailyKey>>,
+}
+
+impl VisitorIds {
+    /// Picks up today's key from before a restart, so visitors already
+    /// counted today keep their ids. A key from another day is replaced.
+    pub fn new(saved: Option<DailyKey>) -> Self {
+        Self {
+            key: Mutex::new(saved),
+        }
+    }
+
+    /// The visitor behind a request. `on_rotate` is handed a new key when
+    /// the day's first visitor makes one, to save it for [`VisitorIds::new`].
+    pub fn visitor(
+        &self,
+        ip: IpAddr,
+        headers: &HeaderMap,
+        on_rotate: impl FnOnce(DailyKey),
+    ) -> Visitor {
+        let today = Utc::now().date_naive();
+        let key = {
+            let mut key = self.key.lock().unwrap_or_else(|e| e.into_inner());
+            match *key {
+                Some((day, key)) if day == today => key,
+                _ => {
+                    let new = (today, rand::rng().random());
+                    *key = Some(new);
+                    on_rotate(new);
+                    tracing::info!("rotated visitor id salt");
+                    new.1
+                }
+            }
+        };
+
+        let user_agent = headers
+            .get(header::USER_AGENT)
+            .map_or(&[][..], |ua| ua.as_bytes());
+
+        let mut hasher = blake3::Hasher::new_keyed(&key);
+        match ip {
+            IpAddr::V4(ip) => hasher.update(&ip.octets()),
+            IpAddr::V6(ip) => hasher.update(&ip.octets()),
+        };
+        // separate the fields so ip/agent boundaries can't be shifted
+        hasher.update(&[0]);
+        hasher.update(user_agent);
+
+        let mut id = [0; 8];
+        id.copy_from_slice(&hasher.finalize().as_bytes()[..8]);
+
+        Visitor {
+            id: UserId::from_le_bytes(id),
+            day: today,
+        }
+    }
+}
+
+/// True if the request carries `DNT: 1` or `Sec-GPC: 1`.
+pub fn tracking_opted_out(headers: &HeaderMap) -> bool {
+    [header::DNT.as_str(), "sec-gpc"].iter().any(|name| {
+        headers
+            .get(*name)
+            .is_some_and(|v| v.as_bytes().trim_ascii() == b"1")
+    })
+}
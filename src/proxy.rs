@@ -0,0 +1,300 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts, State},
+    http::{request::Parts, HeaderMap, Request},
+    middleware::Next,
+    response::Response,
+};
+use ipnet::IpNet;
+
+use crate::error::AppError;
+use crate::listener::Peer;
+
+/// Where a request really came from, after looking through trusted proxies.
+#[derive(Clone, Debug)]
+pub struct ClientInfo {
+    pub ip: IpAddr,
+    /// `http` or `https`, as seen by the client.
+    pub scheme: String,
+    /// The client is a Unix socket peer nothing was resolved behind, so `ip`
+    /// is a stand-in rather than where it really came from.
+    pub unix: bool,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientInfo {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<ClientInfo>()
+            .cloned()
+            .ok_or_else(|| AppError::Internal("client info middleware is missing".to_string()))
+    }
+}
+
+/// Proxies whose forwarding headers we believe. Headers from anyone else are
+/// ignored, otherwise any client could claim to be any address.
+#[derive(Clone, Default)]
+pub struct TrustedProxies {
+    networks: Vec<IpNet>,
+}
+
+impl TrustedProxies {
+    pub fn new(networks: Vec<IpNet>) -> Self {
+        Self { networks }
+    }
+
+    fn trusts(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.networks.iter().any(|net| net.contains(&ip))
+    }
+
+    /// Resolves the client behind `peer`, honouring (in order) `Forwarded`,
+    /// `X-Forwarded-For` / `X-Forwarded-Proto` and `X-Real-IP` when `peer`
+    /// is a trusted proxy.
+    pub fn resolve(&self, peer: Peer, headers: &HeaderMap) -> ClientInfo {
+        let direct = ClientInfo {
+            ip: peer.ip.to_canonical(),
+            scheme: if peer.secure { "https" } else { "http" }.to_string(),
+            unix: peer.unix,
+        };
+        if !self.trusts(peer.ip) {
+            return direct;
+        }
+
+        let forwarded = header_values(headers, "forwarded")
+            .flat_map(|value| {
+                value
+                    .split(',')
+                    .map(parse_forwarded_element)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        if !forwarded.is_empty() {
+            let hops: Vec<Option<IpAddr>> = forwarded.iter().map(|(ip, _)| *ip).collect();
+            let index = self.client_hop(&hops);
+            return ClientInfo {
+                ip: hops[index].unwrap_or(direct.ip),
+                scheme: forwarded[index].1.clone().unwrap_or(direct.scheme),
+                unix: hops[index].is_none() && direct.unix,
+            };
+        }
+
+        let scheme = header_values(headers, "x-forwarded-proto")
+            .flat_map(|value| value.split(','))
+            .next()
+            .map(|proto| proto.trim().to_ascii_lowercase())
+            .filter(|proto| proto == "http" || proto == "https")
+            .unwrap_or(direct.scheme);
+
+        let hops: Vec<Option<IpAddr>> = header_values(headers, "x-forwarded-for")
+            .flat_map(|value| value.split(',').map(parse_ip).collect::<Vec<_>>())
+            .collect();
+        if !hops.is_empty() {
+            let index = self.client_hop(&hops);
+            return ClientInfo {
+                ip: hops[index].unwrap_or(direct.ip),
+                scheme,
+                unix: hops[index].is_none() && direct.unix,
+            };
+        }
+
+        let real_ip = header_values(headers, "x-real-ip")
+            .next()
+            .and_then(parse_ip);
+        ClientInfo {
+            ip: real_ip.unwrap_or(direct.ip),
+            scheme,
+            unix: real_ip.is_none() && direct.unix,
+        }
+    }
+
+    /// Walks the chain from the nearest hop outwards and stops at the first
+    /// address we don't trust, which is the client. Unparseable hops stop the
+    /// walk too, since we can't tell whether they were trusted.
+    fn client_hop(&self, hops: &[Option<IpAddr>]) -> usize {
+        for (i, hop) in hops.iter().enumerate().rev() {
+            match hop {
+                Some(ip) if self.trusts(*ip) && i > 0 => continue,
+                _ => return i,
+            }
+        }
+        0
+    }
+}
+
+fn header_values<'a>(headers: &'a HeaderMap, name: &str) -> impl Iterator<Item = &'a str> {
+    headers
+        .get_all(name)
+        .into_iter()
+        .filter_map(|value| value.to_str().ok())
+}
+
+/// Parses an address as written in forwarding headers: bare, with a port,
+/// or bracketed IPv6 with an optional port.
+fn parse_ip(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+    if let Ok(ip) = value.parse::<IpAddr>() {
+        return Some(ip.to_canonical());
+    }
+    if let Ok(addr) = value.parse::<SocketAddr>() {
+        return Some(addr.ip().to_canonical());
+    }
+    value
+        .strip_prefix('[')
+        .and_then(|v| v.split(']').next())
+        .and_then(|v| v.parse::<IpAddr>().ok())
+        .map(|ip| ip.to_canonical())
+}
+
+/// Parses one RFC 7239 element, e.g. `for="[2001:db8::1]:4711";proto=https`.
+/// Obfuscated or `unknown` nodes come back as `None`.
+fn parse_forwarded_element(element: &str) -> (Option<IpAddr>, Option<String>) {
+    let mut ip = None;
+    let mut proto = None;
+
+    for pair in element.split(';') {
+        let Some((key, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let value = value.trim().trim_matches('"');
+        match key.trim().to_ascii_lowercase().as_str() {
+            "for" => ip = parse_ip(value),
+            "proto" => {
+                let value = value.to_ascii_lowercase();
+                if value == "http" || value == "https" {
+                    proto = Some(value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    (ip, proto)
+}
+
+/// Middleware resolving [`ClientInfo`] for every request, so handlers and
+/// the request log see the real client instead of the proxy.
+///
+/// Takes the peer from [`Peer`] connect info, or axum's plain `SocketAddr`
+/// when the router is mounted elsewhere. Without either, as when called
+/// directly from tests, the peer is an unspecified address.
+pub async fn client_info<B>(
+    State(proxies): State<TrustedProxies>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let extensions = request.extensions();
+    let peer = if let Some(ConnectInfo(peer)) = extensions.get::<ConnectInfo<Peer>>() {
+        *peer
+    } else if let Some(ConnectInfo(addr)) = extensions.get::<ConnectInfo<SocketAddr>>() {
+        Peer {
+            ip: addr.ip(),
+            secure: false,
+            unix: false,
+        }
+    } else {
+        Peer {
+            ip: IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED),
+            secure: false,
+            unix: false,
+        }
+    };
+    let client = proxies.resolve(peer, request.headers());
+    request.extensions_mut().insert(client);
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(ip: &str) -> Peer {
+        Peer {
+            ip: ip.parse().unwrap(),
+            secure: false,
+            unix: false,
+        }
+    }
+
+    fn unix_peer() -> Peer {
+        Peer {
+            unix: true,
+            ..peer("127.0.0.1")
+        }
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn unix_peers_stay_unix_unless_resolved() {
+        let proxies = TrustedProxies::new(vec!["127.0.0.0/8".parse().unwrap()]);
+
+        let direct = proxies.resolve(unix_peer(), &HeaderMap::new());
+        assert!(direct.unix);
+        assert_eq!(direct.ip, "127.0.0.1".parse::<IpAddr>().unwrap());
+
+        let forwarded =
+            proxies.resolve(unix_peer(), &headers(&[("x-forwarded-for", "203.0.113.7")]));
+        assert!(!forwarded.unix);
+        assert_eq!(forwarded.ip, "203.0.113.7".parse::<IpAddr>().unwrap());
+
+        assert!(!proxies.resolve(peer("127.0.0.1"), &HeaderMap::new()).unix);
+    }
+
+    #[test]
+    fn forwarded_chains_stop_at_the_first_untrusted_hop() {
+        let proxies = TrustedProxies::new(vec!["10.0.0.0/8".parse().unwrap()]);
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        // the client may claim anything, only the hops our proxies added count
+        let chain = headers(&[("x-forwarded-for", "1.2.3.4, 203.0.113.7, 10.0.0.2")]);
+        assert_eq!(
+            proxies.resolve(peer("10.0.0.1"), &chain).ip,
+            ip("203.0.113.7")
+        );
+        let chain = headers(&[
+            ("x-forwarded-for", "1.2.3.4"),
+            ("x-forwarded-for", "203.0.113.7, 10.0.0.2"),
+        ]);
+        assert_eq!(
+            proxies.resolve(peer("10.0.0.1"), &chain).ip,
+            ip("203.0.113.7")
+        );
+
+        let chain = headers(&[(
+            "forwarded",
+            "for=1.2.3.4;proto=http, for=\"[2001:db8::1]:4711\";proto=https, for=10.0.0.2",
+        )]);
+        let client = proxies.resolve(peer("10.0.0.1"), &chain);
+        assert_eq!(client.ip, ip("2001:db8::1"));
+        assert_eq!(client.scheme, "https");
+
+        // an obfuscated hop can't be trusted, nor can anything past it
+        let chain = headers(&[("forwarded", "for=1.2.3.4, for=_hidden, for=10.0.0.2")]);
+        assert_eq!(proxies.resolve(peer("10.0.0.1"), &chain).ip, ip("10.0.0.1"));
+
+        // every hop trusted: the furthest one is the client
+        let chain = headers(&[("x-forwarded-for", "10.0.0.3, 10.0.0.2")]);
+        assert_eq!(proxies.resolve(peer("10.0.0.1"), &chain).ip, ip("10.0.0.3"));
+
+        // headers from an untrusted peer are ignored
+        let spoofed = headers(&[
+            ("x-forwarded-for", "1.2.3.4"),
+            ("x-forwarded-proto", "https"),
+        ]);
+        let client = proxies.resolve(peer("198.51.100.9"), &spoofed);
+        assert_eq!(client.ip, ip("198.51.100.9"));
+        assert_eq!(client.scheme, "http");
+    }
+}
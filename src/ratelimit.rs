@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::State,
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+
+use crate::config::{Limit, RateLimitConfig};
+use crate::error::AppError;
+use crate::proxy::ClientInfo;
+use crate::SharedState;
+
+/// How often buckets that have filled back up are dropped.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Routes are limited separately, so a reader pulling in a page's assets
+/// doesn't eat into their page budget.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Class {
+    Pages,
+    Feeds,
+    Assets,
+}
+
+impl Class {
+    const ALL: [Class; 3] = [Class::Pages, Class::Feeds, Class::Assets];
+
+    fn of(path: &str) -> Self {
+        match path {
+            "/rss.xml" | "/sitemap.xml" | "/robots.txt" | "/ai.txt" | "/llms.txt" => Class::Feeds,
+            _ if path.starts_with("/sitemaps/") => Class::Feeds,
+            _ if path.starts_with("/assets/") => Class::Assets,
+            _ => Class::Pages,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Class::Pages => "pages",
+            Class::Feeds => "feeds",
+            Class::Assets => "assets",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// How many tokens a bucket holds. At least one, or a burst of 0 would
+/// never let a request through.
+fn capacity(limit: Limit) -> f64 {
+    limit.burst.max(1) as f64
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Refills for the time passed, then takes a token if there is one.
+    /// Otherwise returns how long until there will be.
+    fn take(&mut self, limit: Limit, now: Instant) -> Result<(), Duration> {
+        let rate = limit.per_minute as f64 / 60.0;
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(capacity(limit));
+        self.updated = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+        }
+    }
+
+    fn is_full(&self, limit: Limit, now: Instant) -> bool {
+        let rate = limit.per_minute as f64 / 60.0;
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens + elapsed * rate >= capacity(limit)
+    }
+}
+
+struct Buckets {
+    map: HashMap<(IpAddr, Class), Bucket>,
+    pruned: Instant,
+}
+
+#[derive(Default)]
+struct Counters {
+    allowed: AtomicU64,
+    limited: AtomicU64,
+}
+
+/// Per class limiter figures, as shown on the stats page.
+#[derive(Serialize)]
+pub struct ClassMetrics {
+    pub class: &'static str,
+    pub per_minute: u32,
+    pub burst: u32,
+    pub allowed: u64,
+    pub limited: u64,
+}
+
+#[derive(Serialize)]
+pub struct LimiterMetrics {
+    /// Clients with a bucket that hasn't filled back up yet.
+    pub tracked_clients: usize,
+    pub classes: Vec<ClassMetrics>,
+}
+
+/// Per client IP token buckets, one per [`Class`].
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Mutex<Buckets>,
+    counters: [Counters; 3],
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            buckets: Mutex::new(Buckets {
+                map: HashMap::new(),
+                pruned: Instant::now(),
+            }),
+            counters: Default::default(),
+        }
+    }
+
+    fn limit(&self, class: Class) -> Limit {
+        match class {
+            Class::Pages => self.config.pages,
+            Class::Feeds => self.config.feeds,
+            Class::Assets => self.config.assets,
+        }
+    }
+
+    fn allowlisted(&self, ip: IpAddr) -> bool {
+        self.config.allowlist.iter().any(|net| net.contains(&ip))
+    }
+
+    /// Takes a token for `ip`, or returns how long it has to wait.
+    pub fn check(&self, ip: IpAddr, path: &str) -> Result<(), Duration> {
+        if !self.config.enabled || self.allowlisted(ip) {
+            return Ok(());
+        }
+
+        let class = Class::of(path);
+        let limit = self.limit(class);
+        if limit.per_minute == 0 {
+            return Ok(());
+        }
+        let now = Instant::now();
+
+        let result = {
+            let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+            if now.duration_since(buckets.pruned) > PRUNE_INTERVAL {
+                buckets
+                    .map
+                    .retain(|(_, class), bucket| !bucket.is_full(self.limit(*class), now));
+                buckets.pruned = now;
+            }
+
+            buckets
+                .map
+                .entry((ip, class))
+                .or_insert(Bucket {
+                    tokens: capacity(limit),
+                    updated: now,
+                })
+                .take(limit, now)
+        };
+
+        let counters = &self.counters[class.index()];
+        match result {
+            Ok(()) => counters.allowed.fetch_add(1, Ordering::Relaxed),
+            Err(_) => counters.limited.fetch_add(1, Ordering::Relaxed),
+        };
+        result
+    }
+
+    pub fn metrics(&self) -> LimiterMetrics {
+        let tracked_clients = {
+            let buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+            let mut ips: Vec<IpAddr> = buckets.map.keys().map(|(ip, _)| *ip).collect();
+            ips.sort_unstable();
+            ips.dedup();
+            ips.len()
+        };
+
+        LimiterMetrics {
+            tracked_clients,
+            classes: Class::ALL
+                .iter()
+                .map(|&class| {
+                    let limit = self.limit(class);
+                    let counters = &self.counters[class.index()];
+                    ClassMetrics {
+                        class: class.name(),
+                        per_minute: limit.per_minute,
+                        burst: limit.burst,
+                        allowed: counters.allowed.load(Ordering::Relaxed),
+                        limited: counters.limited.load(Ordering::Relaxed),
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Middleware answering clients over their limit with `429 Too Many Requests`.
+pub async fn limit<B>(
+    State(state): State<SharedState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(client) = request.extensions().get::<ClientInfo>() else {
+        return next.run(request).await;
+    };
+
+    match state.limiter.check(client.ip, request.uri().path()) {
+        Ok(()) => next.run(request).await,
+        Err(wait) => AppError::TooManyRequests { retry_after: wait }.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burst_of_zero_still_refills() {
+        let limit = Limit {
+            per_minute: 60,
+            burst: 0,
+        };
+        let start = Instant::now();
+        let mut bucket = Bucket {
+            tokens: capacity(limit),
+            updated: start,
+        };
+
+        assert_eq!(bucket.take(limit, start), Ok(()));
+        assert_eq!(bucket.take(limit, start), Err(Duration::from_secs(1)));
+        assert!(!bucket.is_full(limit, start));
+
+        let later = start + Duration::from_secs(1);
+        assert!(bucket.is_full(limit, later));
+        assert_eq!(bucket.take(limit, later), Ok(()));
+    }
+
+    #[test]
+    fn burst_caps_the_tokens() {
+        let limit = Limit {
+            per_minute: 60,
+            burst: 3,
+        };
+        let start = Instant::now();
+        let mut bucket = Bucket {
+            tokens: 0.0,
+            updated: start,
+        };
+
+        let later = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert_eq!(bucket.take(limit, later), Ok(()));
+        }
+        assert!(bucket.take(limit, later).is_err());
+    }
+}
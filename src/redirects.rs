@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use axum::{
+    http::{header, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use color_eyre::eyre::{eyre, Result};
+
+use crate::BlogPost;
+
+/// Old urls and where they live now, answered with a 301 when nothing else
+/// matches the request.
+///
+/// Comes from post `aliases` and the site-level redirects file, a YAML map
+/// of old path to new path. A `*` at the end of an old path matches any
+/// rest, which is appended to the new path when it ends in `*` too:
+///
+/// ```yaml
+/// /posts/hello.html: /blog/hello
+/// /static/*: /assets/*
+/// ```
+#[derive(Debug, Default)]
+pub struct Redirects {
+    exact: HashMap<String, String>,
+    /// Longest prefix first, so the most specific one wins.
+    prefixes: Vec<(String, String)>,
+}
+
+/// Trailing slashes don't make a different page.
+fn normalize(path: &str) -> &str {
+    match path.trim_end_matches('/') {
+        "" => "/",
+        path => path,
+    }
+}
+
+impl Redirects {
+    /// Loads the redirects file if there is one, then adds the aliases of
+    /// `blogposts`. Paths that are also a post stay the post.
+    pub fn load(path: &Path, blogposts: &[BlogPost]) -> Result<Self> {
+        let mut redirects = Self::default();
+
+        if path.exists() {
+            let text = std::fs::read_to_string(path)?;
+            let map: HashMap<String, String> = serde_yaml::from_str(&text).map_err(|err| {
+                eyre!(format!(
+                    "Error parsing redirects ({}): {err}",
+                    path.display()
+                ))
+            })?;
+            for (from, to) in map {
+                match from.strip_suffix('*') {
+                    Some(prefix) => redirects.prefixes.push((prefix.to_string(), to)),
+                    None => {
+                        redirects.exact.insert(normalize(&from).to_string(), to);
+                    }
+                }
+            }
+            redirects
+                .prefixes
+                .sort_by(|a, b| b.0.len().cmp(&a.0.len()).then_with(|| a.0.cmp(&b.0)));
+            tracing::info!(
+                "loaded {} redirect(s) from {}",
+                redirects.exact.len() + redirects.prefixes.len(),
+                path.display()
+            );
+        }
+
+        for post in blogposts {
+            let target = format!("/blog/{}", post.url);
+            for alias in &post.aliases {
+                // bare slugs are old post urls, anything else a full path
+                let from = if alias.starts_with('/') {
+                    normalize(alias).to_string()
+                } else {
+                    format!("/blog/{}", alias.trim_end_matches('/'))
+                };
+                if blogposts.iter().any(|p| format!("/blog/{}", p.url) == from) {
+                    tracing::warn!(
+                        "alias {} of {} is another post, ignoring it",
+                        from,
+                        post.url
+                    );
+                    continue;
+                }
+                if let Some(existing) = redirects.exact.insert(from.clone(), target.clone()) {
+                    if existing != target {
+                        tracing::warn!(
+                            "alias {} of {} replaces redirect to {}",
+                            from,
+                            post.url,
+                            existing
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(redirects)
+    }
+
+    /// Where `path` moved to, if it did.
+    pub fn resolve(&self, path: &str) -> Option<String> {
+        if let Some(to) = self.exact.get(normalize(path)) {
+            return Some(to.clone());
+        }
+        self.prefixes.iter().find_map(|(prefix, to)| {
+            let rest = path.strip_prefix(prefix.as_str())?;
+            Some(match to.strip_suffix('*') {
+                Some(to) => format!("{to}{rest}"),
+                None => to.clone(),
+            })
+        })
+    }
+
+    /// A 301 to wherever `uri` moved to, keeping its query string.
+    pub fn redirect(&self, uri: &Uri) -> Option<Response> {
+        let mut location = self.resolve(uri.path())?;
+        if let Some(query) = uri.query() {
+            location.push(if location.contains('?') { '&' } else { '?' });
+            location.push_str(query);
+        }
+        Some(
+            (
+                StatusCode::MOVED_PERMANENTLY,
+                [(header::LOCATION, location)],
+            )
+                .into_response(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(yaml: &str) -> Redirects {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("redirects.yaml");
+        std::fs::write(&path, yaml).unwrap();
+        Redirects::load(&path, &[]).unwrap()
+    }
+
+    #[test]
+    fn exact_paths_win_over_the_longest_prefix() {
+        let redirects = load(
+            "/posts/hello.html: /blog/hello\n\
+             /old/: /\n\
+             /static/*: /assets/*\n\
+             /static/fonts/*: /assets/type/*\n\
+             /static/fonts/gone.woff2: /assets/type/new.woff2\n\
+             /wiki*: /blog\n",
+        );
+
+        let resolve = |path| redirects.resolve(path);
+        assert_eq!(resolve("/posts/hello.html").as_deref(), Some("/blog/hello"));
+        assert_eq!(
+            resolve("/posts/hello.html/").as_deref(),
+            Some("/blog/hello")
+        );
+        assert_eq!(resolve("/old").as_deref(), Some("/"));
+        assert_eq!(
+            resolve("/static/style.css").as_deref(),
+            Some("/assets/style.css")
+        );
+        assert_eq!(
+            resolve("/static/fonts/a.woff2").as_deref(),
+            Some("/assets/type/a.woff2")
+        );
+        assert_eq!(
+            resolve("/static/fonts/gone.woff2").as_deref(),
+            Some("/assets/type/new.woff2")
+        );
+        assert_eq!(resolve("/wiki/Rust").as_deref(), Some("/blog"));
+        assert_eq!(resolve("/posts/other.html"), None);
+        assert_eq!(resolve("/"), None);
+    }
+
+    #[test]
+    fn redirects_keep_the_query() {
+        let redirects = load("/feed: /rss.xml\n/find: /search?in=posts\n");
+
+        let location = |uri: &str| {
+            let response = redirects.redirect(&uri.parse().unwrap())?;
+            assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+            Some(
+                response.headers()[header::LOCATION]
+                    .to_str()
+                    .unwrap()
+                    .to_string(),
+            )
+        };
+        assert_eq!(location("/feed?utm=x").as_deref(), Some("/rss.xml?utm=x"));
+        assert_eq!(
+            location("/find?q=nix").as_deref(),
+            Some("/search?in=posts&q=nix")
+        );
+        assert_eq!(location("/elsewhere"), None);
+    }
+
+    #[test]
+    fn malformed_files_are_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("redirects.yaml");
+        std::fs::write(&path, "- not\n- a map\n").unwrap();
+        assert!(Redirects::load(&path, &[]).is_err());
+        assert!(Redirects::load(&dir.path().join("missing.yaml"), &[]).is_ok());
+    }
+}
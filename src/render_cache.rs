@@ -0,0 +1,116 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use sha2::{Digest, Sha256};
+
+/// Bump whenever rendering changes in a way the key can't see, like the
+/// math pass, so stale entries are left behind. Upgrades of the rendering
+/// crates are in the key already.
+const VERSION: u32 = 1;
+
+/// Tells apart the temporary files of concurrent writers.
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Rendered post bodies on disk, so restarting with unchanged content skips
+/// comrak, syntect and KaTeX. Entries are keyed by a hash of the markdown
+/// and the renderer configuration, and never go stale: a changed post or
+/// renderer simply misses. Failing to read or write the cache only costs a
+/// render.
+pub struct RenderCache {
+    dir: PathBuf,
+    /// Describes the renderer, hashed into every key.
+    renderer: String,
+    /// Keys looked up since opening, what [`RenderCache::prune`] keeps.
+    used: Mutex<HashSet<String>>,
+}
+
+impl RenderCache {
+    /// A cache in `dir` for the renderer `renderer` describes, e.g. the
+    /// `Debug` output of its options.
+    pub fn new(dir: PathBuf, renderer: &str) -> Self {
+        Self {
+            dir,
+            renderer: format!(
+                "v{VERSION} {} ({}) {renderer}",
+                env!("CARGO_PKG_VERSION"),
+                env!("RENDERER_VERSIONS")
+            ),
+            used: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn key(&self, source: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.renderer.as_bytes());
+        hasher.update([0]);
+        hasher.update(source.as_bytes());
+
+        let mut key = String::with_capacity(64);
+        for byte in hasher.finalize().iter() {
+            let _ = write!(key, "{byte:02x}");
+        }
+        key
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{key}.html"))
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.used
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(key.to_string());
+        std::fs::read_to_string(self.path(key)).ok()
+    }
+
+    /// Stores `html` under `key`. Written next to the entry and renamed into
+    /// place, so a concurrent reader never sees half an entry.
+    pub fn put(&self, key: &str, html: &str) {
+        let path = self.path(key);
+        let tmp = path.with_extension(format!(
+            "html.{}.{}.tmp",
+            std::process::id(),
+            TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let written = std::fs::create_dir_all(&self.dir)
+            .and_then(|_| std::fs::write(&tmp, html))
+            .and_then(|_| std::fs::rename(&tmp, &path));
+        if let Err(err) = written {
+            tracing::warn!(
+                "could not write render cache entry {}: {}",
+                path.display(),
+                err
+            );
+            let _ = std::fs::remove_file(&tmp);
+        }
+    }
+
+    /// Removes the entries nothing looked up since the cache was opened, so
+    /// edited and deleted posts don't pile up. Returns the number removed.
+    pub fn prune(&self) -> usize {
+        let used = self.used.lock().unwrap_or_else(|e| e.into_inner());
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return 0;
+        };
+
+        let mut removed = 0;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            // temporary files may belong to a writer that's still running
+            let stale = entry_key(&path).is_some_and(|key| !used.contains(key));
+            if stale && std::fs::remove_file(&path).is_ok() {
+                removed += 1;
+            }
+        }
+        removed
+    }
+}
+
+/// The key of a finished entry, `None` for temporary files.
+fn entry_key(path: &Path) -> Option<&str> {
+    path.file_name()?.to_str()?.strip_suffix(".html")
+}
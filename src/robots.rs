@@ -0,0 +1,190 @@
+/// A parsed robots.txt, used to check which paths a user agent was told to
+/// stay away from (RFC 9309).
+pub struct RobotsTxt {
+    groups: Vec<Group>,
+}
+
+struct Group {
+    /// As written, matched case-insensitively against user agents.
+    agents: Vec<String>,
+    rules: Vec<Rule>,
+}
+
+struct Rule {
+    allow: bool,
+    pattern: String,
+}
+
+impl RobotsTxt {
+    pub fn parse(text: &str) -> Self {
+        let mut groups: Vec<Group> = Vec::new();
+        let mut in_agents = false;
+
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+
+            match key.trim().to_ascii_lowercase().as_str() {
+                "user-agent" => {
+                    // consecutive user-agent lines share one group
+                    if !in_agents {
+                        groups.push(Group {
+                            agents: Vec::new(),
+                            rules: Vec::new(),
+                        });
+                        in_agents = true;
+                    }
+                    if let Some(group) = groups.last_mut() {
+                        group.agents.push(value.to_string());
+                    }
+                }
+                key @ ("allow" | "disallow") => {
+                    in_agents = false;
+                    // an empty disallow allows everything, same as no rule
+                    if let (Some(group), false) = (groups.last_mut(), value.is_empty()) {
+                        group.rules.push(Rule {
+                            allow: key == "allow",
+                            pattern: value.to_string(),
+                        });
+                    }
+                }
+                _ => in_agents = false,
+            }
+        }
+
+        Self { groups }
+    }
+
+    /// Every named (non-`*`) user agent.
+    pub fn agents(&self) -> impl Iterator<Item = &str> {
+        self.groups
+            .iter()
+            .flat_map(|g| &g.agents)
+            .map(|a| a.as_str())
+            .filter(|a| *a != "*")
+    }
+
+    /// Picks the group for `user_agent`: the longest agent name contained in
+    /// it, falling back to `*`.
+    fn group(&self, user_agent: &str) -> Option<&Group> {
+        let user_agent = user_agent.to_ascii_lowercase();
+        self.groups
+            .iter()
+            .flat_map(|g| g.agents.iter().map(move |a| (a, g)))
+            .filter(|(agent, _)| {
+                *agent != "*" && user_agent.contains(agent.to_ascii_lowercase().as_str())
+            })
+            .max_by_key(|(agent, _)| agent.len())
+            .map(|(_, g)| g)
+            .or_else(|| {
+                self.groups
+                    .iter()
+                    .find(|g| g.agents.iter().any(|a| a == "*"))
+            })
+    }
+
+    /// Whether `user_agent` may fetch `path`. The longest matching rule wins
+    /// and `Allow` wins ties.
+    pub fn is_allowed(&self, user_agent: &str, path: &str) -> bool {
+        let Some(group) = self.group(user_agent) else {
+            return true;
+        };
+
+        group
+            .rules
+            .iter()
+            .filter(|rule| matches(&rule.pattern, path))
+            .max_by_key(|rule| (rule.pattern.len(), rule.allow))
+            .is_none_or(|rule| rule.allow)
+    }
+}
+
+/// Prefix match supporting the `*` wildcard and `$` end anchor.
+fn matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+
+    let mut parts = pattern.split('*');
+    let Some(first) = parts.next() else {
+        return true;
+    };
+    let Some(mut rest) = path.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    for (i, part) in parts.iter().enumerate() {
+        let last = i == parts.len() - 1;
+        if last && anchored {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+
+    !anchored || rest.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards_and_end_anchors() {
+        assert!(matches("/private", "/private/notes"));
+        assert!(!matches("/private", "/public"));
+        assert!(matches("/*.pdf$", "/papers/thesis.pdf"));
+        assert!(!matches("/*.pdf$", "/papers/thesis.pdf?download"));
+        assert!(matches("/fish$", "/fish"));
+        assert!(!matches("/fish$", "/fish/"));
+        assert!(matches("/a*b*c", "/axxbyyc/d"));
+        assert!(!matches("/a*b*c", "/axxcyyb"));
+        assert!(matches("*", "/anything"));
+        assert!(matches("/x*ab$", "/xab"));
+    }
+
+    #[test]
+    fn longest_rule_of_the_closest_group_wins() {
+        let robots = RobotsTxt::parse(
+            "User-agent: *\n\
+             Disallow: /admin\n\
+             Allow: /admin/public\n\
+             Disallow: /*.zip$\n\
+             \n\
+             User-agent: GPTBot\n\
+             User-agent: CCBot\n\
+             Disallow: /\n\
+             Allow: /$\n\
+             \n\
+             User-agent: Tie\n\
+             Disallow: /page\n\
+             Allow: /page\n",
+        );
+
+        let browser = "Mozilla/5.0 Firefox/128.0";
+        assert!(robots.is_allowed(browser, "/blog/post"));
+        assert!(!robots.is_allowed(browser, "/admin/blocklist"));
+        assert!(robots.is_allowed(browser, "/admin/public/x"));
+        assert!(!robots.is_allowed(browser, "/files/site.zip"));
+        assert!(robots.is_allowed(browser, "/files/site.zip.txt"));
+
+        let gpt = "Mozilla/5.0 (compatible; GPTBot/1.2)";
+        assert!(robots.is_allowed(gpt, "/"));
+        assert!(!robots.is_allowed(gpt, "/blog/post"));
+        assert!(!robots.is_allowed("ccbot/2.0", "/admin/public"));
+        assert!(robots.is_allowed("Tie", "/page"));
+
+        assert!(RobotsTxt::parse("").is_allowed(gpt, "/admin"));
+        assert_eq!(
+            robots.agents().collect::<Vec<_>>(),
+            ["GPTBot", "CCBot", "Tie"]
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use axum::{
+    body::{self, Body, HttpBody},
+    extract::State,
+    http::{header, HeaderMap, HeaderName, HeaderValue, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha2::{Digest, Sha256};
+
+use crate::config::SecurityConfig;
+use crate::error::AppError;
+use crate::proxy::ClientInfo;
+use crate::SharedState;
+
+/// Hashes of the inline `<style>` elements and `style` attributes in a page,
+/// so its Content-Security-Policy can allow exactly those and nothing else.
+#[derive(Debug, Default)]
+pub struct InlineStyles {
+    elements: BTreeSet<String>,
+    attributes: BTreeSet<String>,
+}
+
+fn sha256(source: &str) -> String {
+    format!("'sha256-{}'", STANDARD.encode(Sha256::digest(source.as_bytes())))
+}
+
+/// Undoes the escaping maud and comrak apply to attribute values, since the
+/// browser hashes the value it parsed.
+fn unescape(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+impl InlineStyles {
+    pub fn scan(html: &str) -> Self {
+        let mut styles = Self::default();
+
+        let mut rest = html;
+        while let Some(start) = rest.find("<style") {
+            rest = &rest[start + "<style".len()..];
+            // skip the likes of <styles>, then any attributes
+            if !rest.starts_with(['>', ' ', '\t', '\n', '\r']) {
+                continue;
+            }
+            let Some(open) = rest.find('>') else {
+                break;
+            };
+            let Some(close) = rest.find("</style>") else {
+                break;
+            };
+            if open < close {
+                styles.elements.insert(sha256(&rest[open + 1..close]));
+            }
+            rest = &rest[close..];
+        }
+
+        for quote in ['"', '\''] {
+            let pattern = format!("style={quote}");
+            let mut rest = html;
+            while let Some(start) = rest.find(&pattern) {
+                // raw html in posts may put attributes on their own line
+                let attribute = rest[..start].ends_with(|c: char| c.is_ascii_whitespace());
+                rest = &rest[start + pattern.len()..];
+                let Some(end) = rest.find(quote) else {
+                    break;
+                };
+                if attribute {
+                    styles.attributes.insert(sha256(&unescape(&rest[..end])));
+                }
+                rest = &rest[end..];
+            }
+        }
+
+        styles
+    }
+
+    /// `base` with a `style-src` directive for these styles appended.
+    pub fn policy(&self, base: &str) -> String {
+        let mut style_src = String::from("style-src 'self'");
+        for hash in &self.elements {
+            style_src.push(' ');
+            style_src.push_str(hash);
+        }
+        // hashes only cover style attributes alongside 'unsafe-hashes'
+        if !self.attributes.is_empty() {
+            style_src.push_str(" 'unsafe-hashes'");
+            for hash in &self.attributes {
+                style_src.push(' ');
+                style_src.push_str(hash);
+            }
+        }
+
+        let base = base.trim().trim_end_matches(';');
+        if base.is_empty() {
+            style_src
+        } else {
+            format!("{base}; {style_src}")
+        }
+    }
+}
+
+fn insert(headers: &mut HeaderMap, name: HeaderName, value: &str) {
+    if value.is_empty() || headers.contains_key(&name) {
+        return;
+    }
+    match HeaderValue::from_str(value) {
+        Ok(value) => {
+            headers.insert(name, value);
+        }
+        Err(_) => tracing::warn!("invalid {} header value: {:?}", name, value),
+    }
+}
+
+fn hsts(config: &SecurityConfig) -> String {
+    let mut value = format!("max-age={}", config.hsts_max_age);
+    if config.hsts_include_subdomains {
+        value.push_str("; includeSubDomains");
+    }
+    if config.hsts_preload {
+        value.push_str("; preload");
+    }
+    value
+}
+
+/// Adds a Content-Security-Policy to an HTML `response`, `base` with a
+/// `style-src` for its inline styles.
+///
+/// Cached pages carry their [`InlineStyles`] as a response extension. Other
+/// HTML responses are buffered and scanned, unless they are already encoded
+/// or streamed, in which case they get the policy without any inline styles.
+async fn content_security_policy(response: Response, base: &str) -> Response {
+    let is_html = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/html"));
+    if !is_html || base.is_empty() {
+        return response;
+    }
+
+    let mut response = response;
+    let policy = match response.extensions().get::<Arc<InlineStyles>>() {
+        Some(styles) => styles.policy(base),
+        // a streamed body, like the tarpit's trickle, has to go out as it comes
+        None if response.headers().contains_key(header::CONTENT_ENCODING)
+            || response.body().size_hint().exact().is_none() =>
+        {
+            InlineStyles::default().policy(base)
+        }
+        None => {
+            let (parts, body) = response.into_parts();
+            let bytes = match hyper::body::to_bytes(body).await {
+                Ok(bytes) => bytes,
+                Err(err) => {
+                    return AppError::Internal(format!("failed reading response body: {err}"))
+                        .into_response();
+                }
+            };
+            let policy = InlineStyles::scan(&String::from_utf8_lossy(&bytes)).policy(base);
+            response = Response::from_parts(parts, body::boxed(Body::from(bytes)));
+            policy
+        }
+    };
+    insert(
+        response.headers_mut(),
+        header::CONTENT_SECURITY_POLICY,
+        &policy,
+    );
+    response
+}
+
+/// Middleware adding the configured security headers, and a CSP to HTML
+/// pages, see [`content_security_policy`]. It sits inside the compression
+/// layer so it sees bodies before they are compressed.
+pub async fn headers<B>(
+    State(state): State<SharedState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let config = &state.config.security;
+    let https = request
+        .extensions()
+        .get::<ClientInfo>()
+        .is_some_and(|client| client.scheme == "https");
+    let response = next.run(request).await;
+    let mut response = content_security_policy(response, &config.content_security_policy).await;
+
+    let headers = response.headers_mut();
+    insert(headers, header::X_CONTENT_TYPE_OPTIONS, "nosniff");
+    insert(headers, header::REFERRER_POLICY, &config.referrer_policy);
+    insert(
+        headers,
+        HeaderName::from_static("permissions-policy"),
+        &config.permissions_policy,
+    );
+    insert(headers, header::X_FRAME_OPTIONS, &config.frame_options);
+    // browsers ignore HSTS over plain http, and it would be wrong there anyway
+    if https && config.hsts_max_age > 0 {
+        insert(headers, header::STRICT_TRANSPORT_SECURITY, &hsts(config));
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures_util::{stream, StreamExt};
+
+    use super::*;
+
+    const BASE: &str = "default-src 'self'";
+
+    fn html(body: Body) -> Response {
+        Response::builder()
+            .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+            .body(body::boxed(body))
+            .unwrap()
+    }
+
+    fn csp(response: &Response) -> &str {
+        response.headers()[header::CONTENT_SECURITY_POLICY]
+            .to_str()
+            .unwrap()
+    }
+
+    #[test]
+    fn hashes_inline_styles() {
+        let styles = InlineStyles::scan(
+            "<style>p { color: red; }</style><styles>no</styles>\
+             <p style=\"margin: 0 &amp; 1\">a</p><p data-style='x'>b</p>",
+        );
+        assert_eq!(
+            styles.policy("default-src 'self';"),
+            format!(
+                "default-src 'self'; style-src 'self' {} 'unsafe-hashes' {}",
+                sha256("p { color: red; }"),
+                sha256("margin: 0 & 1"),
+            )
+        );
+        assert_eq!(InlineStyles::default().policy(""), "style-src 'self'");
+    }
+
+    #[tokio::test]
+    async fn scans_buffered_pages() {
+        let page = "<html><head><style>body{}</style></head></html>";
+        let response = content_security_policy(html(Body::from(page)), BASE).await;
+        assert_eq!(
+            csp(&response),
+            format!("{BASE}; style-src 'self' {}", sha256("body{}"))
+        );
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, page);
+    }
+
+    #[tokio::test]
+    async fn streamed_pages_are_not_buffered() {
+        // the first chunk, then nothing ever again
+        let chunks =
+            stream::once(async { Ok::<_, std::io::Error>("<html><p>") }).chain(stream::pending());
+        let response = tokio::time::timeout(
+            Duration::from_secs(1),
+            content_security_policy(html(Body::wrap_stream(chunks)), BASE),
+        )
+        .await
+        .expect("waited on the whole body");
+        assert_eq!(csp(&response), format!("{BASE}; style-src 'self'"));
+
+        let mut body = response.into_body();
+        let first = tokio::time::timeout(Duration::from_secs(1), body.data())
+            .await
+            .unwrap();
+        assert_eq!(first.unwrap().unwrap(), "<html><p>");
+    }
+}
@@ -0,0 +1,365 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use axum::{
+    body::HttpBody,
+    http::{header, Response},
+    routing::{get, post},
+    Router,
+};
+use chrono::Utc;
+use color_eyre::eyre::Result;
+use tokio::sync::{RwLock, Semaphore};
+use tokio::time::Duration;
+use tower_http::{
+    catch_panic::CatchPanicLayer,
+    compression::{
+        predicate::{NotForContentType, SizeAbove},
+        CompressionLayer, Predicate,
+    },
+    services::ServeDir,
+    trace::TraceLayer,
+};
+
+use crate::access_log::{self, AccessLog};
+use crate::blocklist::{self, Blocklist};
+use crate::bots::BotClassifier;
+use crate::config::Config;
+use crate::dev::{self, LiveReload};
+use crate::fragments::current_year;
+use crate::handlers::{
+    self, handle_404, handle_ai_txt, handle_babble, handle_blocklist, handle_blog, handle_healthz,
+    handle_llms_txt, handle_login, handle_login_form, handle_metrics, handle_privacy,
+    handle_readyz, handle_robots, handle_rss, handle_search, handle_sitemap, handle_sitemap_part,
+    handle_stats, handle_stats_json, handle_tag, handle_unban, root,
+};
+use crate::metrics::{self, Metrics};
+use crate::privacy::VisitorIds;
+use crate::proxy::{self, ClientInfo, TrustedProxies};
+use crate::ratelimit::{self, RateLimiter};
+use crate::robots::RobotsTxt;
+use crate::views::ViewStore;
+use crate::{assets, error, export, security, telemetry, Content, SharedState, State};
+
+/// How often a served site checks whether a scheduled post is due, or the
+/// year in the footers is over.
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Builds the site from a content root, the directory holding `blog/` and
+/// `assets/`.
+pub struct Site {
+    root: PathBuf,
+    config: Config,
+    dev: bool,
+}
+
+/// Controls a built site: reloads its content and flushes what it buffers.
+#[derive(Clone)]
+pub struct SiteHandle {
+    root: PathBuf,
+    state: SharedState,
+    access_log: Option<Arc<AccessLog>>,
+}
+
+impl Site {
+    /// A site serving `root` with the default config.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            config: Config::default(),
+            dev: false,
+        }
+    }
+
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Development mode: drafts and scheduled posts are served, and the
+    /// content is watched for changes, which reload open pages. Pages show
+    /// what failed to render on top.
+    pub fn dev(mut self, dev: bool) -> Self {
+        self.dev = dev;
+        self
+    }
+
+    /// Loads the content and returns the router serving it, with every
+    /// middleware applied. The router expects [`Peer`](crate::listener::Peer)
+    /// or `SocketAddr` connect info for client addresses, and treats
+    /// requests without either as coming from an unknown address.
+    ///
+    /// Only `data_dir` is written to. Serving for real also needs
+    /// [`SiteHandle::start`].
+    pub async fn build(self) -> Result<(Router, SiteHandle)> {
+        let Site { root, config, dev } = self;
+
+        let access_log = match &config.log.access {
+            Some(access) => Some(Arc::new(AccessLog::open(&config.data_dir, access)?)),
+            None => None,
+        };
+
+        let state = new_state(&root, config, dev).await?;
+        if let Some(live) = &state.dev {
+            live.set_problems(state.content().errors().to_vec());
+        }
+
+        let router = router(&root, state.clone(), access_log.clone());
+        let handle = SiteHandle {
+            root,
+            state,
+            access_log,
+        };
+        Ok((router, handle))
+    }
+
+    /// Renders the site into static files under `out`, see
+    /// [`export`](crate::export::export).
+    pub async fn export(self, out: &Path, base_path: &str) -> Result<usize> {
+        export::export(&self.root, &self.config, out, base_path).await
+    }
+}
+
+impl SiteHandle {
+    /// The content currently served.
+    pub fn content(&self) -> Arc<Content> {
+        self.state.content()
+    }
+
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.state.metrics.clone()
+    }
+
+    /// Writes the precompressed asset siblings and starts what runs in the
+    /// background: reloading when a scheduled post is due or the year turns,
+    /// and in dev mode watching the content for changes.
+    pub async fn start(&self) -> Result<()> {
+        // the nix store is read-only, so failing to write siblings is not fatal
+        let assets_dir = self.root.join("assets");
+        match tokio::task::spawn_blocking(move || assets::precompress_assets(&assets_dir)).await? {
+            Ok(written) => tracing::info!("precompressed {} asset file(s)", written),
+            Err(err) => tracing::warn!("could not precompress assets: {}", err),
+        }
+
+        reload_when_due(self.clone());
+        if let Some(live) = &self.state.dev {
+            let content = vec![
+                self.root.join("blog"),
+                self.root.join(&self.state.config.redirects),
+            ];
+            tracing::info!("dev mode, watching {} for changes", self.root.display());
+            dev::watch(
+                self.clone(),
+                live.clone(),
+                content,
+                self.root.join("assets"),
+            );
+        }
+        Ok(())
+    }
+
+    /// Loads the content root again and swaps it in, rendering only the
+    /// posts that changed. Requests already running finish with the old
+    /// content; on error the old content stays.
+    pub async fn reload(&self) -> Result<()> {
+        let previous = self.state.content();
+        let content = Content::load(
+            &self.root,
+            &self.state.config,
+            &self.state.metrics,
+            Some(&previous),
+            self.state.dev.is_some(),
+        )
+        .await;
+        self.state.metrics.record_reload("content", content.is_ok());
+        let content = content?;
+        tracing::info!("reloaded {} post(s)", content.blogposts.len());
+        self.state.set_content(content);
+        Ok(())
+    }
+
+    /// Ends the dev mode event streams, which would otherwise hold up
+    /// draining connections on shutdown.
+    pub fn close_streams(&self) {
+        if let Some(live) = &self.state.dev {
+            live.close();
+        }
+    }
+
+    /// Writes out buffered view counts and access log lines, waiting for the
+    /// writes to finish.
+    pub fn flush(&self) {
+        self.state.view_store.flush();
+        if let Some(access_log) = &self.access_log {
+            access_log.flush();
+        }
+    }
+}
+
+/// Reloads the content once a scheduled post's date has come, so it goes
+/// live without a restart, and once the year the cached pages show is over.
+fn reload_when_due(handle: SiteHandle) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(RELOAD_CHECK_INTERVAL).await;
+            let content = handle.content();
+            let reason = if content.next_publish().is_some_and(|at| at <= Utc::now()) {
+                "scheduled post"
+            } else if content.year() != current_year() {
+                "new year"
+            } else {
+                continue;
+            };
+            if let Err(err) = handle.reload().await {
+                tracing::error!("reload for {} failed: {}", reason, err);
+            }
+        }
+    });
+}
+
+async fn new_state(path_prefix: &Path, config: Config, dev: bool) -> Result<SharedState> {
+    let metrics = Arc::new(Metrics::default());
+    let content = Content::load(path_prefix, &config, &metrics, None, dev).await?;
+
+    let (view_store, total_views) =
+        ViewStore::open(&config.data_dir.join("stats.db"), &config.stats)?;
+    tracing::info!("loaded view totals for {} post(s)", total_views.len());
+    let visitors = VisitorIds::new(view_store.saved_key());
+
+    // parsed back from the generated file, so what's enforced is exactly what's served
+    let robots = RobotsTxt::parse(&handlers::render_robots(&config.crawlers));
+    let bots = BotClassifier::new(&robots);
+
+    let blocklist = Blocklist::load(
+        config.data_dir.join("blocklist.json"),
+        chrono::Duration::hours(config.blocklist.ban_hours.into()),
+        config.trusted_proxies.clone(),
+    )?;
+
+    Ok(Arc::new(State {
+        content: std::sync::RwLock::new(Arc::new(content)),
+        uptime: chrono::Utc::now(),
+        total_views: RwLock::new(total_views),
+        view_store,
+        visitors,
+        bots,
+        robots,
+        blocklist,
+        limiter: RateLimiter::new(config.rate_limit.clone()),
+        metrics,
+        babble_slots: Arc::new(Semaphore::new(config.babble.max_connections)),
+        dev: dev.then(|| Arc::new(LiveReload::default())),
+        config,
+    }))
+}
+
+/// Compresses only bodies of a known size. Streamed ones, like the dev mode
+/// events and the babble trickle, should reach the client as they're
+/// written rather than sit in the compressor's buffer.
+#[derive(Clone, Copy)]
+struct NotStreamed;
+
+impl Predicate for NotStreamed {
+    fn should_compress<B: HttpBody>(&self, response: &Response<B>) -> bool {
+        response.body().size_hint().exact().is_some()
+            || response.headers().contains_key(header::CONTENT_LENGTH)
+    }
+}
+
+fn router(path_prefix: &Path, state: SharedState, access_log: Option<Arc<AccessLog>>) -> Router {
+    // Responses below this size aren't worth the compression overhead
+    const COMPRESSION_MIN_SIZE: u16 = 256;
+    // tower-http's defaults, then what's compressed already
+    let compression = CompressionLayer::new().compress_when(
+        SizeAbove::new(COMPRESSION_MIN_SIZE)
+            .and(NotForContentType::GRPC)
+            .and(NotForContentType::IMAGES)
+            .and(NotForContentType::const_new("text/event-stream"))
+            .and(NotStreamed)
+            .and(NotForContentType::const_new("application/pdf"))
+            .and(NotForContentType::const_new("font/")),
+    );
+
+    let proxies = TrustedProxies::new(state.config.trusted_proxies.clone());
+    let trace = TraceLayer::new_for_http().make_span_with(|request: &axum::http::Request<_>| {
+        let client = request.extensions().get::<ClientInfo>();
+        let span = tracing::info_span!(
+            "request",
+            method = %request.method(),
+            uri = %request.uri(),
+            client_ip = client.map(|c| tracing::field::display(c.ip)),
+            scheme = client.map(|c| tracing::field::display(&c.scheme)),
+            otel.kind = "server",
+        );
+        telemetry::continue_trace(&span, request.headers());
+        span
+    });
+
+    let mut router = Router::new()
+        .route("/", get(root))
+        .route("/blog/:url", get(handle_blog))
+        .route("/tag/:tag", get(handle_tag))
+        .route("/stats", get(handle_stats))
+        .route("/stats.json", get(handle_stats_json))
+        .route("/privacy", get(handle_privacy))
+        .route("/search", get(handle_search))
+        .route("/admin/login", get(handle_login_form).post(handle_login))
+        .route("/admin/blocklist", get(handle_blocklist))
+        .route("/admin/blocklist/unban", post(handle_unban))
+        .route("/healthz", get(handle_healthz))
+        .route("/readyz", get(handle_readyz))
+        .route("/metrics", get(handle_metrics))
+        .route("/babble", get(handle_babble))
+        .route("/babble/*path", get(handle_babble))
+        .route("/sitemap.xml", get(handle_sitemap))
+        .route("/sitemaps/:file", get(handle_sitemap_part))
+        .route("/rss.xml", get(handle_rss))
+        .route("/robots.txt", get(handle_robots))
+        .route("/ai.txt", get(handle_ai_txt))
+        .route("/llms.txt", get(handle_llms_txt));
+    if state.dev.is_some() {
+        router = router
+            .route(dev::EVENTS_PATH, get(dev::handle_events))
+            .route(dev::SCRIPT_PATH, get(dev::handle_script));
+    }
+
+    let mut router = router
+        .nest_service(
+            "/assets",
+            ServeDir::new(path_prefix.join(Path::new("assets")))
+                .precompressed_br()
+                .precompressed_gzip(),
+        )
+        .fallback(handle_404)
+        .with_state(state.clone())
+        .layer(CatchPanicLayer::custom(error::panic_response));
+    if state.dev.is_some() {
+        router = router.layer(axum::middleware::from_fn(dev::inject));
+    }
+
+    router
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            security::headers,
+        ))
+        .layer(compression)
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            ratelimit::limit,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            blocklist::enforce,
+        ))
+        .layer(axum::middleware::from_fn(error::negotiate))
+        .layer(axum::middleware::from_fn_with_state(state, metrics::track))
+        .layer(trace)
+        .layer(axum::middleware::from_fn_with_state(
+            access_log,
+            access_log::record,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            proxies,
+            proxy::client_info,
+        ))
+}
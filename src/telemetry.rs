@@ -0,0 +1,194 @@
+use axum::http::HeaderMap;
+use color_eyre::eyre::{eyre, Result};
+use opentelemetry::{global, propagation::Extractor, trace::TracerProvider};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    trace::{Sampler, SdkTracer, SdkTracerProvider},
+    Resource,
+};
+use tracing::Subscriber;
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::config::OtlpConfig;
+
+/// Sends spans to an OTLP collector. Dropping it without calling
+/// [`Otlp::shutdown`] loses whatever is still batched.
+pub struct Otlp {
+    provider: SdkTracerProvider,
+}
+
+impl Otlp {
+    /// Sets up the exporter and the W3C trace-context propagator, returning
+    /// the `tracing` layer feeding it.
+    pub fn new<S>(config: &OtlpConfig) -> Result<(Self, OpenTelemetryLayer<S, SdkTracer>)>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(&config.endpoint)
+            .build()
+            .map_err(|err| {
+                eyre!(format!(
+                    "Error creating OTLP exporter ({}): {err}",
+                    config.endpoint
+                ))
+            })?;
+
+        // requests that arrive sampled stay sampled, new traces are sampled
+        // at the configured ratio
+        let sampler =
+            Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sample_ratio)));
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_sampler(sampler)
+            .with_resource(
+                Resource::builder()
+                    .with_service_name(config.service_name.clone())
+                    .build(),
+            )
+            .build();
+
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let tracer = provider.tracer("site");
+
+        Ok((
+            Self { provider },
+            tracing_opentelemetry::layer().with_tracer(tracer),
+        ))
+    }
+
+    /// Exports the spans still batched and stops the exporter.
+    pub fn shutdown(self) {
+        if let Err(err) = self.provider.shutdown() {
+            tracing::warn!("failed flushing spans: {}", err);
+        }
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+/// Continues the trace named by the request's `traceparent` and
+/// `tracestate` headers, if any. Does nothing unless [`Otlp`] installed the
+/// propagator.
+pub fn continue_trace(span: &tracing::Span, headers: &HeaderMap) {
+    let parent =
+        global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+    // fails when the span is disabled, nothing to attach to then
+    let _ = span.set_parent(parent);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use tracing_subscriber::prelude::*;
+
+    use super::*;
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    const PARENT_ID: &str = "00f067aa0ba902b7";
+
+    /// Stands in for an OTLP collector: answers every request with a 200 and
+    /// hands over the bodies.
+    fn collector() -> (String, mpsc::Receiver<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}/v1/traces", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { continue };
+                let tx = tx.clone();
+                std::thread::spawn(move || serve(stream, tx));
+            }
+        });
+        (endpoint, rx)
+    }
+
+    fn serve(stream: std::net::TcpStream, bodies: mpsc::Sender<Vec<u8>>) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut stream = stream;
+        loop {
+            let mut length = 0;
+            let mut line = String::new();
+            loop {
+                line.clear();
+                if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                    return;
+                }
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            let _ = bodies.send(body);
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .unwrap();
+        }
+    }
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|w| w == needle)
+    }
+
+    #[test]
+    fn exports_spans_continuing_the_incoming_trace() {
+        let (endpoint, bodies) = collector();
+        let config = OtlpConfig {
+            endpoint,
+            service_name: "site-test".to_string(),
+            sample_ratio: 0.0,
+        };
+        let (otlp, layer) = Otlp::new(&config).unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            format!("00-{TRACE_ID}-{PARENT_ID}-01").parse().unwrap(),
+        );
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+            let span = tracing::info_span!("request");
+            continue_trace(&span, &headers);
+            span.in_scope(|| tracing::info_span!("render").in_scope(|| {}));
+        });
+        otlp.shutdown();
+
+        // new traces aren't sampled at 0, so these are only here because
+        // they continue the caller's sampled trace
+        let body = bodies.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(contains(&body, b"site-test"));
+        assert!(contains(&body, b"request"));
+        assert!(contains(&body, b"render"));
+        assert!(contains(&body, &hex(TRACE_ID)));
+        assert!(contains(&body, &hex(PARENT_ID)));
+    }
+}
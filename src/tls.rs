@@ -0,0 +1,311 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use axum::{
+    http::{header, uri::Authority, HeaderMap, Uri},
+    response::{IntoResponse, Redirect, Response},
+    Router,
+};
+use color_eyre::eyre::{eyre, Result};
+use rustls::{
+    crypto::{ring, CryptoProvider},
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    ServerConfig,
+};
+use tokio_rustls::TlsAcceptor;
+
+use crate::config::TlsConfig;
+use crate::error::AppError;
+use crate::listener::{Incoming, Listener, Peer};
+use crate::metrics::Metrics;
+
+/// The certificate and key from disk, swapped out when either file changes
+/// so renewed certificates apply without a restart.
+pub struct CertStore {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    provider: Arc<CryptoProvider>,
+    current: RwLock<(Arc<CertifiedKey>, Option<SystemTime>)>,
+}
+
+impl fmt::Debug for CertStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CertStore")
+            .field("cert_path", &self.cert_path)
+            .field("key_path", &self.key_path)
+            .finish()
+    }
+}
+
+/// The later modification time of the two files, which is when the pair
+/// last changed.
+fn modified(cert_path: &Path, key_path: &Path) -> Option<SystemTime> {
+    let cert = std::fs::metadata(cert_path)
+        .and_then(|m| m.modified())
+        .ok()?;
+    let key = std::fs::metadata(key_path)
+        .and_then(|m| m.modified())
+        .ok()?;
+    Some(cert.max(key))
+}
+
+fn load(cert_path: &Path, key_path: &Path, provider: &CryptoProvider) -> Result<CertifiedKey> {
+    let chain = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| {
+            eyre!(format!(
+                "Error reading certificate ({}): {err}",
+                cert_path.display()
+            ))
+        })?;
+    if chain.is_empty() {
+        return Err(eyre!(format!(
+            "No certificate found in {}",
+            cert_path.display()
+        )));
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(|err| {
+        eyre!(format!(
+            "Error reading private key ({}): {err}",
+            key_path.display()
+        ))
+    })?;
+
+    CertifiedKey::from_der(chain, key, provider)
+        .map_err(|err| eyre!(format!("Error loading certificate: {err}")))
+}
+
+impl CertStore {
+    pub fn open(config: &TlsConfig) -> Result<Self> {
+        let provider = Arc::new(ring::default_provider());
+        let modified = modified(&config.cert, &config.key);
+        let key = load(&config.cert, &config.key, &provider)?;
+
+        Ok(Self {
+            cert_path: config.cert.clone(),
+            key_path: config.key.clone(),
+            provider,
+            current: RwLock::new((Arc::new(key), modified)),
+        })
+    }
+
+    /// Reloads the pair if it changed since the last load. A pair that fails
+    /// to load, say halfway through being replaced, keeps the old one in use
+    /// and is retried next time.
+    fn reload_if_changed(&self, metrics: &Metrics) {
+        let modified = modified(&self.cert_path, &self.key_path);
+        let last = self.current.read().unwrap_or_else(|e| e.into_inner()).1;
+        if modified.is_none() || modified == last {
+            return;
+        }
+
+        match load(&self.cert_path, &self.key_path, &self.provider) {
+            Ok(key) => {
+                *self.current.write().unwrap_or_else(|e| e.into_inner()) =
+                    (Arc::new(key), modified);
+                metrics.record_reload("certificate", true);
+                tracing::info!("reloaded certificate {}", self.cert_path.display());
+            }
+            Err(err) => {
+                metrics.record_reload("certificate", false);
+                tracing::warn!("keeping the current certificate: {}", err);
+            }
+        }
+    }
+
+    /// Checks the files for changes every `interval`.
+    pub fn watch(self: &Arc<Self>, interval: Duration, metrics: Arc<Metrics>) {
+        let store = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let store = store.clone();
+                let metrics = metrics.clone();
+                let _ =
+                    tokio::task::spawn_blocking(move || store.reload_if_changed(&metrics)).await;
+            }
+        });
+    }
+
+    /// A rustls config serving this store's certificate, offering HTTP/2
+    /// and HTTP/1.1 over ALPN.
+    pub fn acceptor(self: &Arc<Self>) -> Result<TlsAcceptor> {
+        let mut config = ServerConfig::builder_with_provider(self.provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|err| eyre!(format!("Error configuring TLS: {err}")))?
+            .with_no_client_auth()
+            .with_cert_resolver(self.clone());
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+impl ResolvesServerCert for CertStore {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(
+            self.current
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .0
+                .clone(),
+        )
+    }
+}
+
+/// Sends plain HTTP requests to the same url over https. `https_port` is
+/// left out of the url when it's the default.
+async fn redirect(https_port: u16, headers: HeaderMap, uri: Uri) -> Response {
+    let Some(host) = headers.get(header::HOST).and_then(|h| h.to_str().ok()) else {
+        return AppError::BadRequest("The request has no Host header.".to_string()).into_response();
+    };
+    // drop the port the plain listener was reached on
+    let Ok(authority) = host.parse::<Authority>() else {
+        return AppError::BadRequest("The Host header is invalid.".to_string()).into_response();
+    };
+    let host = authority.host();
+    let path = uri.path_and_query().map_or("/", |p| p.as_str());
+
+    let location = match https_port {
+        443 => format!("https://{host}{path}"),
+        port => format!("https://{host}:{port}{path}"),
+    };
+    Redirect::permanent(&location).into_response()
+}
+
+/// Runs the plain HTTP listener that only redirects to https.
+pub async fn serve_redirect(listen: String, https_port: u16) -> Result<()> {
+    let listener = Listener::bind(&listen).await?;
+    let app = Router::new()
+        .fallback(move |headers: HeaderMap, uri: Uri| redirect(https_port, headers, uri));
+
+    axum::Server::builder(Incoming::new(listener, None))
+        .serve(app.into_make_service_with_connect_info::<Peer>())
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use axum::{http::StatusCode, routing::get};
+    use rustls::{pki_types::ServerName, ClientConfig, RootCertStore};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_rustls::{client::TlsStream, TlsConnector};
+
+    use super::*;
+
+    /// Writes a fresh self-signed pair for `localhost` over the config's
+    /// files, stamped `modified`, and returns the certificate.
+    fn write_pair(config: &TlsConfig, modified: SystemTime) -> CertificateDer<'static> {
+        let pair = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        std::fs::write(&config.cert, pair.cert.pem()).unwrap();
+        std::fs::write(&config.key, pair.signing_key.serialize_pem()).unwrap();
+        for path in [&config.cert, &config.key] {
+            let file = File::options().write(true).open(path).unwrap();
+            file.set_modified(modified).unwrap();
+        }
+        pair.cert.der().clone()
+    }
+
+    /// Connects trusting only `cert`, offering `alpn`.
+    async fn connect(
+        port: u16,
+        cert: &CertificateDer<'static>,
+        alpn: &[&[u8]],
+    ) -> TlsStream<TcpStream> {
+        let mut roots = RootCertStore::empty();
+        roots.add(cert.clone()).unwrap();
+        let mut config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        TlsConnector::from(Arc::new(config))
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await
+            .unwrap()
+    }
+
+    fn served_cert(stream: &TlsStream<TcpStream>) -> &CertificateDer<'static> {
+        &stream.get_ref().1.peer_certificates().unwrap()[0]
+    }
+
+    #[tokio::test]
+    async fn serves_tls_and_picks_up_renewed_certificates() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = TlsConfig {
+            cert: dir.path().join("cert.pem"),
+            key: dir.path().join("key.pem"),
+            reload_interval_secs: 60,
+            redirect_http: None,
+        };
+        let first = write_pair(&config, SystemTime::UNIX_EPOCH + Duration::from_secs(1));
+        let store = Arc::new(CertStore::open(&config).unwrap());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let incoming = Incoming::new(Listener::Tcp(listener), Some(store.acceptor().unwrap()));
+        let app = Router::new().route("/", get(|| async { "ok" }));
+        tokio::spawn(
+            axum::Server::builder(incoming)
+                .serve(app.into_make_service_with_connect_info::<Peer>()),
+        );
+
+        let stream = connect(port, &first, &[b"h2", b"http/1.1"]).await;
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+        assert_eq!(served_cert(&stream), &first);
+
+        // nothing changed, nothing to reload
+        store.reload_if_changed(&Metrics::default());
+        assert_eq!(served_cert(&connect(port, &first, &[b"h2"]).await), &first);
+
+        let second = write_pair(&config, SystemTime::UNIX_EPOCH + Duration::from_secs(2));
+        store.reload_if_changed(&Metrics::default());
+        let mut stream = connect(port, &second, &[b"http/1.1"]).await;
+        assert_eq!(served_cert(&stream), &second);
+
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.ends_with("ok"), "{response}");
+    }
+
+    #[tokio::test]
+    async fn redirects_to_https() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, "example.com:8080".parse().unwrap());
+        let uri: Uri = "/blog/post?q=1".parse().unwrap();
+
+        let response = redirect(443, headers.clone(), uri.clone()).await;
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "https://example.com/blog/post?q=1"
+        );
+
+        let response = redirect(8443, headers, uri).await;
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "https://example.com:8443/blog/post?q=1"
+        );
+
+        let response = redirect(443, HeaderMap::new(), Uri::from_static("/")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
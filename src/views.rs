@@ -0,0 +1,777 @@
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::http::{header, HeaderMap};
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use color_eyre::eyre::Result;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+
+use crate::config::StatsConfig;
+use crate::privacy::{tracking_opted_out, DailyKey, Visitor};
+use crate::{State, UserId};
+
+// How often the writer thread prunes records past their retention window.
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Referrers come from any client, so only this many new domains are tallied
+/// a day. Domains already tallied that day keep counting.
+const MAX_REFERRERS_PER_DAY: i64 = 500;
+
+/// Unique views of a single post.
+///
+/// `count` starts at the persisted total, `seen` only dedupes visitors for
+/// the current day since ids from other days can't be compared anyway. It
+/// starts out with today's views from disk, so a restart doesn't count
+/// anyone twice.
+#[derive(Default)]
+pub struct PostViews {
+    count: usize,
+    day: Option<NaiveDate>,
+    seen: HashSet<UserId>,
+}
+
+impl PostViews {
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Returns true if this visitor hadn't been counted before today.
+    pub fn record(&mut self, visitor: Visitor) -> bool {
+        if self.day != Some(visitor.day) {
+            self.day = Some(visitor.day);
+            self.seen.clear();
+        }
+
+        let new = self.seen.insert(visitor.id);
+        if new {
+            self.count += 1;
+        }
+        new
+    }
+}
+
+enum Event {
+    /// A unique view of a post.
+    View {
+        post: String,
+        visitor: UserId,
+        at: DateTime<Utc>,
+    },
+    /// A page fetched by a crawler or script, tallied per bot and page.
+    BotHit {
+        agent: String,
+        path: String,
+        at: DateTime<Utc>,
+    },
+    /// Any page hit, only kept as daily referrer and entry page tallies.
+    Visit {
+        path: String,
+        referrer: Option<String>,
+        entry: bool,
+        at: DateTime<Utc>,
+    },
+}
+
+enum Message {
+    Event(Event),
+    /// Replaces the saved visitor id key.
+    Key(DailyKey),
+    Flush(mpsc::Sender<()>),
+}
+
+#[derive(Serialize)]
+pub struct Count {
+    pub name: String,
+    pub hits: u64,
+}
+
+#[derive(Serialize)]
+pub struct PostReport {
+    pub url: String,
+    /// Filled in by the caller, the store only knows urls.
+    pub title: String,
+    pub total: u64,
+    /// Over [`Report::days`], older tallies are pruned.
+    pub bot_hits: u64,
+    /// Aligned with [`Report::days`].
+    pub daily: Vec<u64>,
+    /// Aligned with [`Report::weeks`].
+    pub weekly: Vec<u64>,
+}
+
+/// Time series and tallies for the stats dashboard, read from disk.
+#[derive(Serialize)]
+pub struct Report {
+    pub days: Vec<String>,
+    pub daily_views: Vec<u64>,
+    pub weeks: Vec<String>,
+    pub weekly_views: Vec<u64>,
+    /// Aligned with [`Report::days`].
+    pub daily_bot_hits: Vec<u64>,
+    pub posts: Vec<PostReport>,
+    pub bots: Vec<Count>,
+    pub referrers: Vec<Count>,
+    pub entry_pages: Vec<Count>,
+}
+
+/// Domain of an external `Referer`, or `None` if the header is missing,
+/// malformed or points back at this site.
+pub fn referrer_domain(headers: &HeaderMap) -> Option<String> {
+    let referer = headers.get(header::REFERER)?.to_str().ok()?;
+    let (_, rest) = referer.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit('@').next()?;
+    let domain = host.split(':').next()?.to_ascii_lowercase();
+    let domain = domain.strip_prefix("www.").unwrap_or(&domain).to_string();
+
+    let own_host = headers
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.split(':').next())
+        .map(|h| h.to_ascii_lowercase());
+    let own_host = own_host
+        .as_deref()
+        .map(|h| h.strip_prefix("www.").unwrap_or(h));
+
+    if !is_domain(&domain) || own_host == Some(domain.as_str()) {
+        return None;
+    }
+    Some(domain)
+}
+
+/// Whether `name` looks like a DNS name, at most 253 characters of labels
+/// made of letters, digits and hyphens.
+fn is_domain(name: &str) -> bool {
+    name.len() <= 253
+        && name.contains('.')
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        })
+}
+
+/// Tallies a hit on a tracked page, as a bot hit or as a visit. Returns the
+/// visitor if the hit should count towards unique views, i.e. it came from
+/// a browser that hasn't opted out of tracking.
+pub fn track_hit(state: &State, path: &str, ip: IpAddr, headers: &HeaderMap) -> Option<Visitor> {
+    if let Some(bot) = state.bots.classify(headers) {
+        state.view_store.bot_hit(&bot.name, path);
+        return None;
+    }
+    if tracking_opted_out(headers) {
+        return None;
+    }
+
+    state.view_store.visit(path, headers);
+    Some(
+        state
+            .visitors
+            .visitor(ip, headers, |key| state.view_store.save_key(key)),
+    )
+}
+
+/// On-disk view statistics backed by SQLite.
+///
+/// Views are handed to a writer thread over a channel and written in
+/// batches, so recording one never touches the disk on the request path.
+pub struct ViewStore {
+    tx: mpsc::Sender<Message>,
+    reader: Mutex<Connection>,
+}
+
+impl ViewStore {
+    /// Opens (or creates) the database and returns the store along with the
+    /// lifetime view totals per post url.
+    pub fn open(path: &Path, config: &StatsConfig) -> Result<(Self, HashMap<String, PostViews>)> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS views (
+                 post TEXT NOT NULL,
+                 visitor INTEGER NOT NULL,
+                 ts INTEGER NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS views_ts ON views (ts);
+             CREATE TABLE IF NOT EXISTS daily_views (
+                 post TEXT NOT NULL,
+                 day TEXT NOT NULL,
+                 views INTEGER NOT NULL,
+                 PRIMARY KEY (post, day)
+             );
+             CREATE TABLE IF NOT EXISTS daily_bot_hits (
+                 agent TEXT NOT NULL,
+                 path TEXT NOT NULL,
+                 day TEXT NOT NULL,
+                 hits INTEGER NOT NULL,
+                 PRIMARY KEY (agent, path, day)
+             );
+             CREATE TABLE IF NOT EXISTS daily_referrers (
+                 domain TEXT NOT NULL,
+                 day TEXT NOT NULL,
+                 hits INTEGER NOT NULL,
+                 PRIMARY KEY (domain, day)
+             );
+             CREATE INDEX IF NOT EXISTS daily_referrers_day ON daily_referrers (day);
+             CREATE TABLE IF NOT EXISTS daily_entries (
+                 path TEXT NOT NULL,
+                 day TEXT NOT NULL,
+                 hits INTEGER NOT NULL,
+                 PRIMARY KEY (path, day)
+             );
+             CREATE TABLE IF NOT EXISTS visitor_key (
+                 day TEXT NOT NULL,
+                 key BLOB NOT NULL
+             );",
+        )?;
+
+        let totals = {
+            let mut stmt =
+                conn.prepare("SELECT post, SUM(views) FROM daily_views GROUP BY post")?;
+            let rows = stmt.query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })?;
+
+            let mut totals = HashMap::new();
+            for row in rows {
+                let (post, count) = row?;
+                totals.insert(
+                    post,
+                    PostViews {
+                        count: count as usize,
+                        ..Default::default()
+                    },
+                );
+            }
+
+            let today = Utc::now().date_naive();
+            let midnight = today.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp();
+            let mut stmt = conn.prepare("SELECT post, visitor FROM views WHERE ts >= ?1")?;
+            let rows = stmt.query_map(params![midnight], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })?;
+            for row in rows {
+                let (post, visitor) = row?;
+                let views = totals.entry(post).or_default();
+                views.day = Some(today);
+                views.seen.insert(visitor as UserId);
+            }
+            totals
+        };
+
+        // WAL lets the dashboard read while the writer thread writes
+        let reader = Connection::open(path)?;
+
+        let (tx, rx) = mpsc::channel();
+        let retention = chrono::Duration::days(config.retention_days.into());
+        let bot_retention = chrono::Duration::days(config.bot_hits_retention_days.into());
+        let batch_size = config.batch_size.max(1);
+        let flush_interval = Duration::from_secs(config.flush_interval_secs);
+
+        std::thread::Builder::new()
+            .name("view-store".to_string())
+            .spawn(move || {
+                writer(
+                    conn,
+                    rx,
+                    batch_size,
+                    flush_interval,
+                    retention,
+                    bot_retention,
+                )
+            })?;
+
+        Ok((
+            ViewStore {
+                tx,
+                reader: Mutex::new(reader),
+            },
+            totals,
+        ))
+    }
+
+    /// Queues a unique view for writing, never blocks.
+    pub fn record(&self, post: &str, visitor: UserId) {
+        self.send(Event::View {
+            post: post.to_string(),
+            visitor,
+            at: Utc::now(),
+        });
+    }
+
+    /// Queues a page hit for the referrer and entry page tallies. A hit is an
+    /// entry if it didn't come from another page on this site.
+    pub fn visit(&self, path: &str, headers: &HeaderMap) {
+        let referrer = referrer_domain(headers);
+        let internal = headers.contains_key(header::REFERER) && referrer.is_none();
+        self.send(Event::Visit {
+            path: path.to_string(),
+            referrer,
+            entry: !internal,
+            at: Utc::now(),
+        });
+    }
+
+    /// Queues a page fetched by the named bot.
+    pub fn bot_hit(&self, agent: &str, path: &str) {
+        self.send(Event::BotHit {
+            agent: agent.to_string(),
+            path: path.to_string(),
+            at: Utc::now(),
+        });
+    }
+
+    fn send(&self, event: Event) {
+        if self.tx.send(Message::Event(event)).is_err() {
+            tracing::error!("view store writer is gone, dropping event");
+        }
+    }
+
+    /// The visitor id key saved by [`ViewStore::save_key`], if any.
+    pub fn saved_key(&self) -> Option<DailyKey> {
+        let conn = self.reader.lock().unwrap_or_else(|e| e.into_inner());
+        let (day, key) = conn
+            .query_row("SELECT day, key FROM visitor_key", [], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+            })
+            .ok()?;
+        let day = NaiveDate::parse_from_str(&day, "%Y-%m-%d").ok()?;
+        Some((day, key.try_into().ok()?))
+    }
+
+    /// Queues replacing the saved visitor id key, which only lives until
+    /// the next one replaces it.
+    pub fn save_key(&self, key: DailyKey) {
+        if self.tx.send(Message::Key(key)).is_err() {
+            tracing::error!("view store writer is gone, not saving visitor key");
+        }
+    }
+
+    /// Writes out any buffered views and waits for the write to finish.
+    pub fn flush(&self) {
+        let (ack_tx, ack_rx) = mpsc::channel();
+        if self.tx.send(Message::Flush(ack_tx)).is_ok() {
+            let _ = ack_rx.recv();
+        }
+    }
+}
+
+fn writer(
+    mut conn: Connection,
+    rx: mpsc::Receiver<Message>,
+    batch_size: usize,
+    flush_interval: Duration,
+    retention: chrono::Duration,
+    bot_retention: chrono::Duration,
+) {
+    let mut batch: Vec<Event> = Vec::with_capacity(batch_size);
+    let mut last_flush = Instant::now();
+    let mut last_prune: Option<Instant> = None;
+
+    loop {
+        let timeout = flush_interval.saturating_sub(last_flush.elapsed());
+        let mut ack = None;
+        let mut closed = false;
+
+        match rx.recv_timeout(timeout) {
+            Ok(Message::Event(event)) => batch.push(event),
+            Ok(Message::Key((day, key))) => {
+                if let Err(err) = save_key(&mut conn, day, &key) {
+                    tracing::error!("failed saving visitor key: {}", err);
+                }
+            }
+            Ok(Message::Flush(tx)) => ack = Some(tx),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => closed = true,
+        }
+
+        if !batch.is_empty()
+            && (batch.len() >= batch_size
+                || last_flush.elapsed() >= flush_interval
+                || ack.is_some()
+                || closed)
+        {
+            if let Err(err) = write_batch(&mut conn, &batch) {
+                tracing::error!("failed writing {} view event(s): {}", batch.len(), err);
+            }
+            batch.clear();
+        }
+        if batch.is_empty() {
+            last_flush = Instant::now();
+        }
+
+        if last_prune.is_none_or(|t| t.elapsed() >= RETENTION_INTERVAL) {
+            let cutoff = (Utc::now() - retention).timestamp();
+            match conn.execute("DELETE FROM views WHERE ts < ?1", params![cutoff]) {
+                Ok(0) => {}
+                Ok(n) => tracing::info!("pruned {} view record(s) past retention", n),
+                Err(err) => tracing::error!("failed pruning view records: {}", err),
+            }
+            let cutoff = day_key((Utc::now() - retention).date_naive());
+            for (table, what) in [
+                ("daily_referrers", "referrer"),
+                ("daily_entries", "entry page"),
+            ] {
+                match conn.execute(
+                    &format!("DELETE FROM {table} WHERE day < ?1"),
+                    params![cutoff],
+                ) {
+                    Ok(0) => {}
+                    Ok(n) => tracing::info!("pruned {} {} tally(s) past retention", n, what),
+                    Err(err) => tracing::error!("failed pruning {} tallies: {}", what, err),
+                }
+            }
+            let cutoff = day_key((Utc::now() - bot_retention).date_naive());
+            match conn.execute("DELETE FROM daily_bot_hits WHERE day < ?1", params![cutoff]) {
+                Ok(0) => {}
+                Ok(n) => tracing::info!("pruned {} bot hit tally(s) past retention", n),
+                Err(err) => tracing::error!("failed pruning bot hit tallies: {}", err),
+            }
+            last_prune = Some(Instant::now());
+        }
+
+        if let Some(tx) = ack {
+            let _ = tx.send(());
+        }
+        if closed {
+            break;
+        }
+    }
+}
+
+fn write_batch(conn: &mut Connection, batch: &[Event]) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    {
+        let mut insert_view =
+            tx.prepare_cached("INSERT INTO views (post, visitor, ts) VALUES (?1, ?2, ?3)")?;
+        let mut bump_daily = tx.prepare_cached(
+            "INSERT INTO daily_views (post, day, views) VALUES (?1, ?2, 1)
+             ON CONFLICT (post, day) DO UPDATE SET views = views + 1",
+        )?;
+        let mut bump_bot = tx.prepare_cached(
+            "INSERT INTO daily_bot_hits (agent, path, day, hits) VALUES (?1, ?2, ?3, 1)
+             ON CONFLICT (agent, path, day) DO UPDATE SET hits = hits + 1",
+        )?;
+        let mut bump_referrer = tx.prepare_cached(
+            "INSERT INTO daily_referrers (domain, day, hits) SELECT ?1, ?2, 1
+             WHERE (SELECT COUNT(*) FROM daily_referrers WHERE day = ?2) < ?3
+                OR EXISTS (SELECT 1 FROM daily_referrers WHERE domain = ?1 AND day = ?2)
+             ON CONFLICT (domain, day) DO UPDATE SET hits = hits + 1",
+        )?;
+        let mut bump_entry = tx.prepare_cached(
+            "INSERT INTO daily_entries (path, day, hits) VALUES (?1, ?2, 1)
+             ON CONFLICT (path, day) DO UPDATE SET hits = hits + 1",
+        )?;
+
+        for event in batch {
+            match event {
+                Event::View { post, visitor, at } => {
+                    insert_view.execute(params![post, *visitor as i64, at.timestamp()])?;
+                    bump_daily.execute(params![post, day_key(at.date_naive())])?;
+                }
+                Event::BotHit { agent, path, at } => {
+                    bump_bot.execute(params![agent, path, day_key(at.date_naive())])?;
+                }
+                Event::Visit {
+                    path,
+                    referrer,
+                    entry,
+                    at,
+                } => {
+                    let day = day_key(at.date_naive());
+                    if let Some(domain) = referrer {
+                        bump_referrer.execute(params![domain, day, MAX_REFERRERS_PER_DAY])?;
+                    }
+                    if *entry {
+                        bump_entry.execute(params![path, day])?;
+                    }
+                }
+            }
+        }
+    }
+    tx.commit()
+}
+
+fn save_key(conn: &mut Connection, day: NaiveDate, key: &[u8; 32]) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute("DELETE FROM visitor_key", [])?;
+    tx.execute(
+        "INSERT INTO visitor_key (day, key) VALUES (?1, ?2)",
+        params![day_key(day), &key[..]],
+    )?;
+    tx.commit()
+}
+
+fn day_key(day: NaiveDate) -> String {
+    day.format("%Y-%m-%d").to_string()
+}
+
+fn week_key(day: NaiveDate) -> String {
+    day.format("%G-W%V").to_string()
+}
+
+impl ViewStore {
+    /// Builds the dashboard report over the last `days` days and the last
+    /// `weeks` ISO weeks, from what the writer has written so far. That lags
+    /// the post pages by at most the flush interval. Reads the database, so
+    /// call this off the async runtime.
+    pub fn report(&self, days: u32, weeks: u32, top: usize) -> rusqlite::Result<Report> {
+        let conn = self.reader.lock().unwrap_or_else(|e| e.into_inner());
+
+        let today = Utc::now().date_naive();
+        let week_start =
+            |d: NaiveDate| d - chrono::Days::new(d.weekday().num_days_from_monday().into());
+
+        let day_list: Vec<NaiveDate> = (0..days)
+            .rev()
+            .map(|i| today - chrono::Days::new(i.into()))
+            .collect();
+        let week_list: Vec<NaiveDate> = (0..weeks)
+            .rev()
+            .map(|i| week_start(today) - chrono::Days::new(7 * u64::from(i)))
+            .collect();
+
+        let first_day = day_list
+            .first()
+            .copied()
+            .unwrap_or(today)
+            .min(week_list.first().copied().unwrap_or(today));
+
+        let day_index: HashMap<String, usize> = day_list
+            .iter()
+            .enumerate()
+            .map(|(i, d)| (day_key(*d), i))
+            .collect();
+        let week_index: HashMap<String, usize> = week_list
+            .iter()
+            .enumerate()
+            .map(|(i, d)| (week_key(*d), i))
+            .collect();
+
+        let mut posts: HashMap<String, PostReport> = HashMap::new();
+        {
+            let mut stmt =
+                conn.prepare("SELECT post, SUM(views) FROM daily_views GROUP BY post")?;
+            let rows = stmt.query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })?;
+            for row in rows {
+                let (url, total) = row?;
+                posts.insert(
+                    url.clone(),
+                    PostReport {
+                        title: url.clone(),
+                        url,
+                        total: total as u64,
+                        bot_hits: 0,
+                        daily: vec![0; day_list.len()],
+                        weekly: vec![0; week_list.len()],
+                    },
+                );
+            }
+        }
+
+        let mut daily_views = vec![0; day_list.len()];
+        let mut weekly_views = vec![0; week_list.len()];
+        {
+            let mut stmt =
+                conn.prepare("SELECT post, day, views FROM daily_views WHERE day >= ?1")?;
+            let rows = stmt.query_map(params![day_key(first_day)], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i64>(2)?,
+                ))
+            })?;
+            for row in rows {
+                let (url, day, views) = row?;
+                let views = views as u64;
+                let Ok(date) = NaiveDate::parse_from_str(&day, "%Y-%m-%d") else {
+                    continue;
+                };
+                let day_slot = day_index.get(&day).copied();
+                let week_slot = week_index.get(&week_key(date)).copied();
+
+                if let Some(i) = day_slot {
+                    daily_views[i] += views;
+                }
+                if let Some(i) = week_slot {
+                    weekly_views[i] += views;
+                }
+                if let Some(post) = posts.get_mut(&url) {
+                    if let Some(i) = day_slot {
+                        post.daily[i] += views;
+                    }
+                    if let Some(i) = week_slot {
+                        post.weekly[i] += views;
+                    }
+                }
+            }
+        }
+
+        let mut daily_bot_hits = vec![0; day_list.len()];
+        {
+            let mut stmt = conn.prepare(
+                "SELECT path, day, SUM(hits) FROM daily_bot_hits WHERE day >= ?1
+                 GROUP BY path, day",
+            )?;
+            let since = day_key(day_list.first().copied().unwrap_or(today));
+            let rows = stmt.query_map(params![since], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i64>(2)?,
+                ))
+            })?;
+            for row in rows {
+                let (path, day, hits) = row?;
+                let hits = hits as u64;
+                let Some(&i) = day_index.get(&day) else {
+                    continue;
+                };
+                daily_bot_hits[i] += hits;
+                let post = path
+                    .strip_prefix("/blog/")
+                    .and_then(|url| posts.get_mut(url));
+                if let Some(post) = post {
+                    post.bot_hits += hits;
+                }
+            }
+        }
+
+        let tally = |table: &str, column: &str| -> rusqlite::Result<Vec<Count>> {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {column}, SUM(hits) AS total FROM {table} WHERE day >= ?1
+                 GROUP BY {column} ORDER BY total DESC, {column} LIMIT ?2"
+            ))?;
+            let rows = stmt.query_map(params![day_key(first_day), top as i64], |row| {
+                Ok(Count {
+                    name: row.get(0)?,
+                    hits: row.get::<_, i64>(1)? as u64,
+                })
+            })?;
+            rows.collect()
+        };
+
+        let mut posts: Vec<PostReport> = posts.into_values().collect();
+        posts.sort_by(|a, b| b.total.cmp(&a.total).then_with(|| a.url.cmp(&b.url)));
+
+        Ok(Report {
+            days: day_list.iter().map(|d| day_key(*d)).collect(),
+            daily_views,
+            weeks: week_list.iter().map(|d| week_key(*d)).collect(),
+            weekly_views,
+            daily_bot_hits,
+            posts,
+            bots: tally("daily_bot_hits", "agent")?,
+            referrers: tally("daily_referrers", "domain")?,
+            entry_pages: tally("daily_entries", "path")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn referred(referer: &str) -> Option<String> {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::REFERER, referer.parse().unwrap());
+        headers.insert(header::HOST, "bergsoe.net:443".parse().unwrap());
+        referrer_domain(&headers)
+    }
+
+    #[test]
+    fn referrers_are_plain_domains() {
+        assert_eq!(
+            referred("https://www.Example.com:8443/a?b#c").as_deref(),
+            Some("example.com")
+        );
+        assert_eq!(
+            referred("https://user@news.ycombinator.com/item").as_deref(),
+            Some("news.ycombinator.com")
+        );
+        assert_eq!(referred("https://www.bergsoe.net/blog/x"), None);
+        assert_eq!(referred("https://localhost/"), None);
+        assert_eq!(referred("https://spam.example<script>.com/"), None);
+        assert_eq!(referred("https://a..b/"), None);
+        assert_eq!(referred(&format!("https://{}.com/", "a".repeat(64))), None);
+        assert_eq!(referred("not a url"), None);
+    }
+
+    #[test]
+    fn prunes_referrer_and_entry_tallies_and_caps_new_referrers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stats.db");
+        let config = StatsConfig {
+            retention_days: 30,
+            ..StatsConfig::default()
+        };
+        drop(ViewStore::open(&path, &config).unwrap());
+
+        let today = Utc::now().date_naive();
+        let old = day_key(today - chrono::Days::new(31));
+        let recent = day_key(today - chrono::Days::new(29));
+        let conn = Connection::open(&path).unwrap();
+        for day in [&old, &recent] {
+            conn.execute(
+                "INSERT INTO daily_referrers (domain, day, hits) VALUES ('example.com', ?1, 1)",
+                params![day],
+            )
+            .unwrap();
+            conn.execute(
+                "INSERT INTO daily_entries (path, day, hits) VALUES ('/', ?1, 1)",
+                params![day],
+            )
+            .unwrap();
+        }
+        let today = day_key(today);
+        for i in 0..MAX_REFERRERS_PER_DAY {
+            conn.execute(
+                "INSERT INTO daily_referrers (domain, day, hits) VALUES (?1, ?2, 1)",
+                params![format!("site{i}.example"), today],
+            )
+            .unwrap();
+        }
+
+        let (store, _) = ViewStore::open(&path, &config).unwrap();
+        let mut headers = HeaderMap::new();
+        for referer in ["https://site0.example/", "https://one-too-many.example/"] {
+            headers.insert(header::REFERER, referer.parse().unwrap());
+            store.visit("/", &headers);
+        }
+        store.flush();
+
+        let days = |table: &str| -> Vec<String> {
+            let mut stmt = conn
+                .prepare(&format!("SELECT DISTINCT day FROM {table} ORDER BY day"))
+                .unwrap();
+            let rows = stmt.query_map([], |row| row.get(0)).unwrap();
+            rows.map(|day| day.unwrap()).collect()
+        };
+        assert_eq!(days("daily_referrers"), [recent.clone(), today.clone()]);
+        assert_eq!(days("daily_entries"), [recent, today.clone()]);
+
+        let hits = |domain: &str| -> Option<i64> {
+            conn.query_row(
+                "SELECT hits FROM daily_referrers WHERE domain = ?1 AND day = ?2",
+                params![domain, today],
+                |row| row.get(0),
+            )
+            .ok()
+        };
+        assert_eq!(hits("site0.example"), Some(2));
+        assert_eq!(hits("one-too-many.example"), None);
+    }
+}
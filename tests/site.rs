@@ -0,0 +1,197 @@
+use std::path::Path;
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+    Router,
+};
+use tower::ServiceExt;
+
+use site::config::Config;
+use site::fragments::current_year;
+use site::{Site, SiteHandle};
+
+fn write_post(root: &Path, slug: &str, title: &str, draft: bool) {
+    let post = format!(
+        "---\ntitle: {title:?}\ndate: 01-02-2024\narchived: false\ndraft: {draft}\ntags: [testing]\n---\n\nSome words about {title}.\n"
+    );
+    std::fs::write(root.join("blog").join(format!("{slug}.md")), post).unwrap();
+}
+
+/// A content root with one published post and one draft, and a site built
+/// from it with `config`, keeping its data next to it.
+async fn build(root: &Path, config: Config) -> (Router, SiteHandle) {
+    std::fs::create_dir_all(root.join("blog")).unwrap();
+    std::fs::create_dir_all(root.join("assets")).unwrap();
+    std::fs::write(root.join("assets").join("style.css"), "body { margin: 0; }").unwrap();
+    write_post(root, "hello", "Hello there", false);
+    write_post(root, "unfinished", "Not yet", true);
+
+    let config = Config {
+        data_dir: root.join("data"),
+        ..config
+    };
+    Site::new(root).config(config).build().await.unwrap()
+}
+
+/// The urls of the posts served, sorted.
+fn urls(handle: &SiteHandle) -> Vec<String> {
+    let mut urls: Vec<_> = handle
+        .content()
+        .posts()
+        .iter()
+        .map(|p| p.url().to_string())
+        .collect();
+    urls.sort();
+    urls
+}
+
+async fn send(router: &Router, request: Request<Body>) -> (StatusCode, String) {
+    let response = router.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, String::from_utf8_lossy(&body).into_owned())
+}
+
+async fn get(router: &Router, uri: &str) -> (StatusCode, String) {
+    send(router, Request::get(uri).body(Body::empty()).unwrap()).await
+}
+
+#[tokio::test]
+async fn build_serves_published_posts_only() {
+    let root = tempfile::tempdir().unwrap();
+    let (router, handle) = build(root.path(), Config::default()).await;
+
+    assert_eq!(urls(&handle), ["hello"]);
+    assert!(handle.content().errors().is_empty());
+    assert_eq!(handle.content().year(), current_year());
+
+    let (status, body) = get(&router, "/blog/hello").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("Hello there"));
+    let (status, _) = get(&router, "/blog/unfinished").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, body) = get(&router, "/tag/testing").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("Hello there") && !body.contains("Not yet"));
+
+    // building leaves the content root alone, precompressing is start()'s job
+    let assets = root.path().join("assets");
+    assert!(!assets.join("style.css.br").exists());
+    handle.start().await.unwrap();
+    assert!(assets.join("style.css.br").exists());
+    assert!(assets.join("style.css.gz").exists());
+}
+
+#[tokio::test]
+async fn reload_swaps_in_changed_content() {
+    let root = tempfile::tempdir().unwrap();
+    let (router, handle) = build(root.path(), Config::default()).await;
+
+    write_post(root.path(), "hello", "Hello again", false);
+    write_post(root.path(), "unfinished", "Done now", false);
+    std::fs::write(root.path().join("blog").join("broken.md"), "no frontmatter").unwrap();
+    handle.reload().await.unwrap();
+
+    assert_eq!(urls(&handle), ["hello", "unfinished"]);
+    assert_eq!(handle.content().errors().len(), 1);
+
+    let (status, body) = get(&router, "/blog/hello").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("Hello again") && !body.contains("Hello there"));
+    let (status, body) = get(&router, "/blog/unfinished").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("Done now"));
+}
+
+#[tokio::test]
+async fn reload_keeps_the_old_content_on_error() {
+    let root = tempfile::tempdir().unwrap();
+    let (router, handle) = build(root.path(), Config::default()).await;
+
+    std::fs::write(root.path().join("redirects.yaml"), "[not, a, map]").unwrap();
+    assert!(handle.reload().await.is_err());
+
+    let (status, body) = get(&router, "/blog/hello").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("Hello there"));
+}
+
+#[tokio::test]
+async fn admin_token_stays_out_of_urls() {
+    let root = tempfile::tempdir().unwrap();
+    let config = Config {
+        admin_token: Some("s3cret token".to_string()),
+        ..Config::default()
+    };
+    let (router, _) = build(root.path(), config).await;
+
+    let (status, _) = get(&router, "/admin/blocklist?token=s3cret%20token").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, body) = get(&router, "/admin/login").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("method=\"post\""));
+
+    let login = |token: &str| {
+        Request::post("/admin/login")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(format!("token={token}")))
+            .unwrap()
+    };
+    let (status, _) = send(&router, login("wrong")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let response = router.clone().oneshot(login("s3cret+token")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(response.headers()[header::LOCATION], "/admin/blocklist");
+    let cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+    assert!(cookie.contains("HttpOnly") && cookie.contains("SameSite=Strict"));
+    assert!(!cookie.contains("s3cret"));
+    let session = cookie.split(';').next().unwrap();
+
+    let with = |name, value: &str| {
+        Request::get("/admin/blocklist")
+            .header(name, value)
+            .body(Body::empty())
+            .unwrap()
+    };
+    let (status, body) = send(&router, with(header::COOKIE, session)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("Blocklist") && !body.contains("s3cret"));
+    let (status, _) = send(&router, with(header::AUTHORIZATION, "Bearer s3cret token")).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&router, with(header::COOKIE, "admin_session=00")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let unban = Request::post("/admin/blocklist/unban")
+        .header(header::COOKIE, session)
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(Body::from("ip=203.0.113.7"))
+        .unwrap();
+    let response = router.clone().oneshot(unban).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(response.headers()[header::LOCATION], "/admin/blocklist");
+}
+
+#[tokio::test]
+async fn export_only_lists_exported_pages() {
+    let root = tempfile::tempdir().unwrap();
+    let (router, _) = build(root.path(), Config::default()).await;
+    let (_, served) = get(&router, "/sitemap.xml").await;
+    assert!(served.contains("/stats</loc>"));
+
+    let out = root.path().join("out");
+    let config = Config {
+        data_dir: root.path().join("data"),
+        ..Config::default()
+    };
+    Site::new(root.path())
+        .config(config)
+        .export(&out, "/")
+        .await
+        .unwrap();
+    let exported = std::fs::read_to_string(out.join("sitemap.xml")).unwrap();
+    assert!(exported.contains("/blog/hello</loc>"));
+    assert!(exported.contains("/privacy</loc>") && out.join("privacy").exists());
+    assert!(!exported.contains("/stats"));
+}